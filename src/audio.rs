@@ -0,0 +1,43 @@
+use bevy::prelude::*;
+use crate::game_assets::GameAssets;
+use crate::states::GameState;
+use crate::target::EnemyLeaked;
+use crate::tower::{TowerBuilt, TowerSold, TowerUpgraded};
+
+/// Plays the sound effects of gameplay events
+pub struct GameAudioPlugin;
+
+impl Plugin for GameAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system_set(
+                SystemSet::on_update(GameState::Gameplay)
+                    .with_system(play_leak_sound)
+                    .with_system(play_tower_sounds)
+            )
+        ;
+    }
+}
+
+fn play_leak_sound(
+    mut enemy_leaked: EventReader<EnemyLeaked>,
+    audio: Res<Audio>,
+    assets: Res<GameAssets>,
+) {
+    // One pop is enough when a bunch of enemies get through at once
+    if enemy_leaked.iter().last().is_some() {
+        audio.play(assets.enemy_death_sounds.clone());
+    }
+}
+
+fn play_tower_sounds(
+    mut tower_built: EventReader<TowerBuilt>,
+    mut tower_upgraded: EventReader<TowerUpgraded>,
+    mut tower_sold: EventReader<TowerSold>,
+    audio: Res<Audio>,
+    assets: Res<GameAssets>,
+) {
+    if tower_built.iter().count() + tower_upgraded.iter().count() + tower_sold.iter().count() > 0 {
+        audio.play(assets.tower_place_sound.clone());
+    }
+}
@@ -0,0 +1,76 @@
+use std::process::ExitCode;
+use serde_derive::Serialize;
+use tower_power::helpers::{LevelIssue, Root, Severity, validate_level};
+
+const USAGE: &str = "usage: validate-level [--json] <level.ldtk>...";
+
+/// Everything wrong with one level file
+#[derive(Serialize)]
+struct FileReport {
+    file: String,
+    /// False when there is at least one error, warnings are fine
+    valid: bool,
+    issues: Vec<LevelIssue>,
+}
+
+fn validate_file(file: &str) -> FileReport {
+    let issues: Vec<LevelIssue> = match Root::from_file(file) {
+        Err(e) => vec![LevelIssue::error(None, e.to_string(), None)],
+        Ok(root) => root.levels
+            .iter()
+            .flat_map(|level| validate_level(level, &root.defs))
+            .collect(),
+    };
+
+    FileReport {
+        file: file.to_string(),
+        valid: !issues.iter().any(|i| i.severity == Severity::Error),
+        issues,
+    }
+}
+
+fn print_report(report: &FileReport) {
+    for issue in &report.issues {
+        let severity = match issue.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let level = issue.level.as_ref().map(|l| format!(" [{}]", l)).unwrap_or_default();
+        let cell = issue.cell.map(|(x, y)| format!(" at ({}, {})", x, y)).unwrap_or_default();
+        println!("{}{}: {}: {}{}", report.file, level, severity, issue.message, cell);
+    }
+    if report.issues.is_empty() {
+        println!("{}: ok", report.file);
+    }
+}
+
+fn main() -> ExitCode {
+    let mut json = false;
+    let mut files = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    }
+
+    let reports: Vec<FileReport> = files.iter().map(|f| validate_file(f)).collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports).expect("reports are always valid json"));
+    } else {
+        reports.iter().for_each(print_report);
+    }
+
+    match reports.iter().all(|r| r.valid) {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use crate::damage::{Damage, DamageType};
 use crate::states::GameState;
 
 pub struct BulletPlugin;
@@ -8,6 +9,8 @@ impl Plugin for BulletPlugin {
         app
             .register_type::<Bullet>()
             .register_type::<Lifetime>()
+            .register_type::<Damage>()
+            .register_type::<DamageType>()
             .add_system_set(
                 SystemSet::on_update(GameState::Gameplay)
                     .with_system(move_bullets)
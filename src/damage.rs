@@ -0,0 +1,155 @@
+use bevy::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use crate::target::Health;
+
+/// Share of the damage left after resistances that always gets through armour
+pub const MIN_DAMAGE_SHARE: f32 = 0.1;
+
+/// Explosions only have to get through this share of the armour
+pub const EXPLOSIVE_ARMOUR_SHARE: f32 = 0.5;
+
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DamageType {
+    #[default]
+    Physical,
+    Energy,
+    Explosive,
+}
+
+/// What a hit does to whatever it hits, put it on bullets and other damage sources
+#[derive(Component, Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[reflect(Component)]
+#[serde(default)]
+pub struct Damage {
+    pub amount: f32,
+    pub damage_type: DamageType,
+}
+
+impl Damage {
+    pub fn new(amount: f32, damage_type: DamageType) -> Self {
+        Damage { amount, damage_type }
+    }
+}
+
+/// Share of each damage type an enemy ignores, 1 makes it immune and negative values make it weak to it
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Resistances {
+    pub physical: f32,
+    pub energy: f32,
+    pub explosive: f32,
+}
+
+impl Resistances {
+    pub fn get(&self, damage_type: DamageType) -> f32 {
+        match damage_type {
+            DamageType::Physical => self.physical,
+            DamageType::Energy => self.energy,
+            DamageType::Explosive => self.explosive,
+        }
+    }
+}
+
+/// Protection of an enemy, an enemy without it takes every hit in full
+#[derive(Component, Reflect, Clone, Copy, Debug, Default, PartialEq)]
+#[reflect(Component)]
+pub struct Armour {
+    /// Taken off every physical hit, explosions only feel part of it and energy goes right through
+    pub value: f32,
+    pub resistances: Resistances,
+}
+
+/// How much health a hit takes off, every damage source goes through here.
+///
+/// Resistances scale the hit first, armour is then taken off what is left.
+/// Armour never stops more than all but `MIN_DAMAGE_SHARE` of a hit, only a resistance of 1 does.
+pub fn resolve_damage(damage: &Damage, armour: &Armour) -> f32 {
+    let resistance = armour.resistances.get(damage.damage_type).clamp(-1.0, 1.0);
+    let resisted = damage.amount.max(0.0) * (1.0 - resistance);
+
+    let armour_value = armour.value.max(0.0) * match damage.damage_type {
+        DamageType::Physical => 1.0,
+        DamageType::Explosive => EXPLOSIVE_ARMOUR_SHARE,
+        DamageType::Energy => 0.0,
+    };
+    (resisted - armour_value).max(resisted * MIN_DAMAGE_SHARE)
+}
+
+/// Applies a hit to `health`, returns the health it took off
+pub fn deal_damage(health: &mut Health, armour: Option<&Armour>, damage: &Damage) -> f32 {
+    let dealt = resolve_damage(damage, armour.unwrap_or(&Armour::default()));
+    health.value -= dealt;
+    dealt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn armour(value: f32, physical: f32, energy: f32, explosive: f32) -> Armour {
+        Armour { value, resistances: Resistances { physical, energy, explosive } }
+    }
+
+    #[test]
+    fn unarmoured_targets_take_full_damage() {
+        for damage_type in [DamageType::Physical, DamageType::Energy, DamageType::Explosive] {
+            assert_eq!(resolve_damage(&Damage::new(3.0, damage_type), &Armour::default()), 3.0);
+        }
+    }
+
+    #[test]
+    fn armour_is_taken_off_physical_hits() {
+        assert_eq!(resolve_damage(&Damage::new(3.0, DamageType::Physical), &armour(1.0, 0.0, 0.0, 0.0)), 2.0);
+    }
+
+    #[test]
+    fn explosions_feel_half_the_armour_and_energy_ignores_it() {
+        let armour = armour(2.0, 0.0, 0.0, 0.0);
+        assert_eq!(resolve_damage(&Damage::new(4.0, DamageType::Explosive), &armour), 3.0);
+        assert_eq!(resolve_damage(&Damage::new(4.0, DamageType::Energy), &armour), 4.0);
+    }
+
+    #[test]
+    fn resistances_scale_before_armour() {
+        // 4 * (1 - 0.5) = 2, minus 1 armour
+        assert_eq!(resolve_damage(&Damage::new(4.0, DamageType::Physical), &armour(1.0, 0.5, 0.0, 0.0)), 1.0);
+        // Only the matching resistance counts
+        assert_eq!(resolve_damage(&Damage::new(4.0, DamageType::Energy), &armour(0.0, 0.5, 0.0, 0.0)), 4.0);
+    }
+
+    #[test]
+    fn negative_resistance_is_a_weakness() {
+        assert_eq!(resolve_damage(&Damage::new(2.0, DamageType::Energy), &armour(0.0, 0.0, -0.5, 0.0)), 3.0);
+        // Weaknesses are capped at double damage
+        assert_eq!(resolve_damage(&Damage::new(2.0, DamageType::Energy), &armour(0.0, 0.0, -5.0, 0.0)), 4.0);
+    }
+
+    #[test]
+    fn heavy_armour_still_lets_some_damage_through() {
+        let dealt = resolve_damage(&Damage::new(2.0, DamageType::Physical), &armour(10.0, 0.0, 0.0, 0.0));
+        assert!((dealt - 2.0 * MIN_DAMAGE_SHARE).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn full_resistance_is_immunity() {
+        assert_eq!(resolve_damage(&Damage::new(5.0, DamageType::Explosive), &armour(0.0, 0.0, 0.0, 1.0)), 0.0);
+        assert_eq!(resolve_damage(&Damage::new(5.0, DamageType::Explosive), &armour(0.0, 0.0, 0.0, 3.0)), 0.0);
+    }
+
+    #[test]
+    fn negative_damage_does_not_heal() {
+        assert_eq!(resolve_damage(&Damage::new(-5.0, DamageType::Physical), &Armour::default()), 0.0);
+    }
+
+    #[test]
+    fn dealing_damage_lowers_health() {
+        let mut health = Health { value: 4.0, max: 4.0 };
+        let dealt = deal_damage(&mut health, Some(&armour(1.0, 0.0, 0.0, 0.0)), &Damage::new(3.0, DamageType::Physical));
+        assert_eq!(dealt, 2.0);
+        assert_eq!(health.value, 2.0);
+
+        deal_damage(&mut health, None, &Damage::new(3.0, DamageType::Energy));
+        assert_eq!(health.value, -1.0);
+    }
+}
@@ -0,0 +1,249 @@
+use bevy::prelude::*;
+use crate::game_assets::GameAssets;
+use crate::gameplay::{Campaign, GameMap, MapLoadErrorMessage};
+use crate::helpers::spawn_button;
+use crate::states::GameState;
+use crate::physics::ProjectileHit;
+use crate::target::EnemyKilled;
+use crate::waves::WaveState;
+
+pub struct GameOverPlugin;
+
+impl Plugin for GameOverPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<RunStats>()
+            .add_event::<GameOverEvent>()
+            .add_event::<VictoryEvent>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Gameplay)
+                    .with_system(reset_run_stats)
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Gameplay)
+                    .with_system(track_run_stats)
+                    .with_system(enter_game_over.after(track_run_stats))
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::GameOver)
+                    .with_system(spawn_game_over_ui)
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::GameOver)
+                    .with_system(retry_button_click)
+                    .with_system(main_menu_button_click)
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::GameOver)
+                    .with_system(despawn_game_over_ui)
+            )
+        ;
+    }
+}
+
+/// Send this when the player ran out of lives
+pub struct GameOverEvent;
+
+/// Send this when the last wave of the last level has been cleared
+pub struct VictoryEvent;
+
+/// How the current attempt at the level went, reset every time a level starts
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct RunStats {
+    pub waves_survived: usize,
+    pub kills: u32,
+    pub money_earned: u32,
+    /// Health taken off enemies by projectiles
+    pub damage_dealt: f32,
+    /// The campaign was beaten rather than lost
+    pub victory: bool,
+}
+
+#[derive(Component)]
+pub struct GameOverUiRoot;
+
+#[derive(Component)]
+pub struct RetryButton;
+
+#[derive(Component)]
+pub struct MainMenuButton;
+
+fn reset_run_stats(
+    mut commands: Commands,
+) {
+    commands.insert_resource(RunStats::default());
+}
+
+fn track_run_stats(
+    mut stats: ResMut<RunStats>,
+    mut enemy_killed: EventReader<EnemyKilled>,
+    mut hits: EventReader<ProjectileHit>,
+) {
+    for event in enemy_killed.iter() {
+        stats.kills += 1;
+        stats.money_earned += event.bounty;
+    }
+    for hit in hits.iter() {
+        stats.damage_dealt += hit.damage;
+    }
+}
+
+/// Puts the game over screen on top of the level, which freezes everything in it.
+/// The same screen celebrates beating the campaign.
+fn enter_game_over(
+    mut game_over: EventReader<GameOverEvent>,
+    mut victory: EventReader<VictoryEvent>,
+    mut stats: ResMut<RunStats>,
+    mut game_state: ResMut<State<GameState>>,
+    waves: Res<WaveState>,
+) {
+    let lost = game_over.iter().last().is_some();
+    let won = victory.iter().last().is_some();
+    if !lost && !won {
+        return;
+    }
+
+    stats.victory = won && !lost;
+    stats.waves_survived = match stats.victory {
+        true => waves.current,
+        // The wave that got through does not count
+        false => waves.current.saturating_sub(1),
+    };
+    match stats.victory {
+        true => info!("Victory after {} waves, {} kills", stats.waves_survived, stats.kills),
+        false => info!("Game over after {} waves, {} kills", stats.waves_survived, stats.kills),
+    }
+    if let Err(e) = game_state.push(GameState::GameOver) {
+        warn!("Could not show the game over screen: {:?}", e);
+    }
+}
+
+fn spawn_game_over_ui(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    stats: Res<RunStats>,
+) {
+    let (title, background, retry, retry_color) = match stats.victory {
+        true => ("- Victory -", Color::rgba(0.0, 0.2, 0.05, 0.6), "Play Again", Color::DARK_GREEN),
+        false => ("- Game Over -", Color::rgba(0.2, 0.0, 0.0, 0.6), "Retry", Color::RED),
+    };
+    let retry_button = spawn_button(&mut commands, &assets, retry, retry_color);
+    commands.entity(retry_button).insert(RetryButton);
+
+    let menu_button = spawn_button(&mut commands, &assets, "Main Menu", Color::MIDNIGHT_BLUE);
+    commands.entity(menu_button).insert(MainMenuButton);
+
+    let text_style = |font_size| TextStyle {
+        font: assets.game_font.clone(),
+        font_size,
+        color: Color::ANTIQUE_WHITE,
+    };
+
+    commands.spawn(NodeBundle {
+        style: Style {
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            position_type: PositionType::Absolute,
+            justify_content: JustifyContent::Center,
+            flex_direction: FlexDirection::Column,
+            ..default()
+        },
+        background_color: background.into(),
+        ..default()
+    }).insert((Name::new("Game_over_ui_root"), GameOverUiRoot))
+        .with_children(|commands| {
+            commands
+                .spawn(TextBundle {
+                    style: Style {
+                        align_self: AlignSelf::Center,
+                        margin: UiRect::bottom(Val::Percent(5.0)),
+                        ..default()
+                    },
+                    text: Text::from_section(title, text_style(96.0)),
+                    ..default()
+                });
+
+            for line in [
+                format!("Waves survived: {}", stats.waves_survived),
+                format!("Kills: {}", stats.kills),
+                format!("Money earned: {}", stats.money_earned),
+                format!("Damage dealt: {:.0}", stats.damage_dealt),
+            ] {
+                commands
+                    .spawn(TextBundle {
+                        style: Style {
+                            align_self: AlignSelf::Center,
+                            margin: UiRect::bottom(Val::Percent(1.0)),
+                            ..default()
+                        },
+                        text: Text::from_section(line, text_style(32.0)),
+                        ..default()
+                    });
+            }
+        })
+        .add_child(retry_button)
+        .add_child(menu_button)
+    ;
+}
+
+fn despawn_game_over_ui(
+    mut commands: Commands,
+    roots: Query<Entity, With<GameOverUiRoot>>,
+) {
+    for root in &roots {
+        commands.entity(root).despawn_recursive();
+    }
+}
+
+/// Rebuilds the map from the level file so towers and blocked cells of the last run are gone
+fn fresh_map(commands: &mut Commands, campaign: &Campaign, map: &mut GameMap) -> bool {
+    match campaign.current_map() {
+        Ok(fresh) => {
+            *map = fresh;
+            true
+        }
+        Err(e) => {
+            error!("Failed to reload the level! {}", e);
+            commands.insert_resource(MapLoadErrorMessage(format!("Failed to reload the level: {}", e)));
+            false
+        }
+    }
+}
+
+fn retry_button_click(
+    mut commands: Commands,
+    interactions: Query<&Interaction, (With<RetryButton>, Changed<Interaction>)>,
+    mut game_state: ResMut<State<GameState>>,
+    mut mouse_input: ResMut<Input<MouseButton>>,
+    campaign: Res<Campaign>,
+    mut map: ResMut<GameMap>,
+) {
+    for interaction in &interactions {
+        if matches!(interaction, Interaction::Clicked) {
+            // Unwinds the stack, leaving and entering Gameplay tears the level down and builds it again
+            let next = match fresh_map(&mut commands, &campaign, &mut map) {
+                true => GameState::Gameplay,
+                false => GameState::MainMenu,
+            };
+            game_state.replace(next).unwrap();
+            mouse_input.clear();
+        }
+    }
+}
+
+fn main_menu_button_click(
+    mut commands: Commands,
+    interactions: Query<&Interaction, (With<MainMenuButton>, Changed<Interaction>)>,
+    mut game_state: ResMut<State<GameState>>,
+    mut mouse_input: ResMut<Input<MouseButton>>,
+    campaign: Res<Campaign>,
+    mut map: ResMut<GameMap>,
+) {
+    for interaction in &interactions {
+        if matches!(interaction, Interaction::Clicked) {
+            fresh_map(&mut commands, &campaign, &mut map);
+            game_state.replace(GameState::MainMenu).unwrap();
+            mouse_input.clear();
+        }
+    }
+}
@@ -1,25 +1,57 @@
+use bevy::asset::LoadState;
+use bevy::math::Vec3Swizzles;
 use bevy::pbr::NotShadowCaster;
 use bevy::pbr::wireframe::Wireframe;
 use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
 use bevy_mod_picking::{Highlighting, PickableBundle};
+use crate::game_assets::GameAssets;
+use crate::gameover::VictoryEvent;
+use crate::helpers::{CellGrid, CellType, FlowField, ground_chunks, ground_mesh, LdtkLoader, LdtkProject, LevelSelector, MapLoadError, PathGraph, Root, WaveSet};
 use crate::states::GameState;
+use crate::target::MovementMode;
+use crate::tower::Tower;
+
+/// Relative to the assets folder
+pub const CAMPAIGN_FILE: &str = "levels/test.ldtk";
 
 pub struct GameplayPlugin;
 
 impl Plugin for GameplayPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_event::<LevelCompleteEvent>()
+            .add_event::<GroundChangedEvent>()
+            .add_event::<LevelReloadedEvent>()
+            .add_asset::<LdtkProject>()
+            .init_asset_loader::<LdtkLoader>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Loading)
+                    .with_system(wait_for_campaign)
+            )
             .add_system_set(
                 SystemSet::on_enter(GameState::Gameplay)
                     //.with_system(load_assets)
                     .with_system(spawn_basic_scene)
             )
+            .add_system_set(
+                SystemSet::on_update(GameState::Gameplay)
+                    .with_system(advance_level)
+                    .with_system(rebuild_ground_chunks)
+            )
+            // Designers can edit the level while it is being played, or while sitting in a menu
+            .add_system(reload_campaign)
+            .add_system(rebuild_level_scene.after(reload_campaign))
+            .add_system_set(
+                SystemSet::on_exit(GameState::Gameplay)
+                    .with_system(despawn_level)
+            )
             .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
         ;
     }
 }
 
-#[derive(Resource, Reflect, Default)]
+#[derive(Resource, Reflect, Default, Clone)]
 #[reflect(Resource)]
 pub struct GameMap {
     pub starting_lives: u32,
@@ -27,28 +59,491 @@ pub struct GameMap {
     pub name: String,
     pub width: f32,
     pub height: f32,
-    pub waypoints: Vec<Vec2>,
+    /// Every lane from the Spawns to the Finishes
+    #[reflect(ignore)]
+    pub paths: PathGraph,
     pub grid_size: u32,
+    #[reflect(ignore)]
+    pub cells: CellGrid,
+    /// How enemies get from the Spawns to a Finish on this level
+    pub movement: MovementMode,
+    /// Distance to a Finish over the walkable cells, used by pathfinding enemies
+    #[reflect(ignore)]
+    pub flow_field: FlowField,
+    /// Enemies walk over any open ground and towers block their way
+    pub mazing: bool,
+    /// Cells towers have been built on, with the type they had before
+    #[reflect(ignore)]
+    pub blocked: HashMap<UVec2, CellType>,
+    #[reflect(ignore)]
+    pub waves: WaveSet,
 }
 
+impl GameMap {
+    /// World space (x, z) of the middle of a grid cell
+    pub fn cell_center(&self, cell: UVec2) -> Vec2 {
+        let cell_size = self.grid_size as f32;
+        (cell.as_vec2() * cell_size) + Vec2::splat(cell_size / 2.0)
+    }
+
+    /// Grid cell under a world space (x, z) position
+    pub fn world_to_cell(&self, position: Vec2) -> Option<UVec2> {
+        let cell = (position / self.grid_size as f32).floor();
+        if cell.x < 0. || cell.y < 0. || cell.x >= self.cells.width as f32 || cell.y >= self.cells.height as f32 {
+            return None;
+        }
+        Some(cell.as_uvec2())
+    }
+
+    pub fn cell_height(&self, cell: UVec2) -> f32 {
+        self.cells
+            .get(cell.x as usize, cell.y as usize)
+            .map(|c| c.height())
+            .unwrap_or(0.0)
+    }
+
+    /// Height of the terrain at a world space (x, z) position, 0 outside the map
+    pub fn height_at(&self, position: Vec2) -> f32 {
+        self.world_to_cell(position)
+            .map(|cell| self.cell_height(cell))
+            .unwrap_or(0.0)
+    }
+
+    /// Cells the Spawn and Finish entities sit on
+    fn endpoint_cells(&self) -> (Vec<UVec2>, Vec<UVec2>) {
+        let cells = |nodes: Vec<usize>| nodes
+            .iter()
+            .filter_map(|&n| self.world_to_cell(self.paths.nodes[n].position))
+            .collect::<Vec<_>>();
+        let finishes = (0..self.paths.nodes.len()).filter(|&n| self.paths.is_finish(n)).collect();
+        (cells(self.paths.spawns.clone()), cells(finishes))
+    }
+
+    /// Can pathfinding enemies walk over this cell, `endpoints` are the cells of the Spawns and Finishes
+    fn is_walkable(&self, cell: UVec2, endpoints: &HashSet<UVec2>) -> bool {
+        if self.blocked.contains_key(&cell) {
+            return false;
+        }
+        match self.cells.get(cell.x as usize, cell.y as usize) {
+            None | Some(CellType::Raised) => false,
+            Some(CellType::Path) => true,
+            // When mazing any open ground will do
+            Some(_) if self.mazing => true,
+            Some(_) => endpoints.contains(&cell),
+        }
+    }
+
+    /// Blocks a cell for a new tower and paints it as buildable ground. Refused (returning false) when it
+    /// would cut every Spawn or one of the `occupied` cells enemies are standing on off from every Finish.
+    pub fn try_block_cell(&mut self, cell: UVec2, occupied: &[UVec2], ground_changed: &mut EventWriter<GroundChangedEvent>) -> bool {
+        let cell_type = match self.cells.get(cell.x as usize, cell.y as usize) {
+            Some(cell_type) => cell_type,
+            None => return false,
+        };
+        if occupied.contains(&cell) || self.blocked.contains_key(&cell) {
+            return false;
+        }
+        self.blocked.insert(cell, cell_type);
+        self.rebuild_flow_field();
+
+        let (spawns, _) = self.endpoint_cells();
+        let cut_off = spawns
+            .iter()
+            .chain(occupied)
+            .any(|c| self.flow_field.distance(*c).is_none());
+        if cut_off {
+            self.blocked.remove(&cell);
+            self.rebuild_flow_field();
+            return false;
+        }
+        self.set_cell_type(cell, CellType::Buildable, ground_changed);
+        true
+    }
+
+    /// Opens a blocked cell up for enemies again, giving it back the type it had before the tower
+    pub fn unblock_cell(&mut self, cell: UVec2, ground_changed: &mut EventWriter<GroundChangedEvent>) {
+        if let Some(cell_type) = self.blocked.remove(&cell) {
+            if !self.set_cell_type(cell, cell_type, ground_changed) {
+                self.rebuild_flow_field();
+            }
+        }
+    }
+
+    /// Rebuilds the flow field, call this whenever the walkable cells change
+    pub fn rebuild_flow_field(&mut self) {
+        let (spawns, finishes) = self.endpoint_cells();
+        let endpoints: HashSet<UVec2> = spawns.into_iter().chain(finishes.iter().copied()).collect();
+        self.flow_field = FlowField::compute(self.cells.width, self.cells.height, &finishes, |cell| self.is_walkable(cell, &endpoints));
+    }
+
+    /// Changes a cell, keeping the pathfinding up to date and getting the ground around it rebuilt.
+    /// Returns false when the cell already had that type or is outside the map.
+    pub fn set_cell_type(&mut self, cell: UVec2, cell_type: CellType, ground_changed: &mut EventWriter<GroundChangedEvent>) -> bool {
+        match self.cells.set(cell.x as usize, cell.y as usize, cell_type) {
+            Some(previous) if previous != cell_type => {
+                self.rebuild_flow_field();
+                ground_changed.send(GroundChangedEvent { cell });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Can a tower be built on this cell: a buildable one, or when mazing any open ground
+    /// except the Spawns and Finishes
+    pub fn is_tower_slot(&self, cell: UVec2) -> bool {
+        match self.cells.get(cell.x as usize, cell.y as usize) {
+            None | Some(CellType::Raised) => false,
+            Some(cell_type) if !self.mazing => cell_type == CellType::Buildable,
+            Some(_) => {
+                let (spawns, finishes) = self.endpoint_cells();
+                !spawns.contains(&cell) && !finishes.contains(&cell)
+            }
+        }
+    }
+
+    /// Where tower bases go up front, one per buildable cell.
+    /// None when mazing, the cell to build on is picked on the ground instead.
+    pub fn tower_slots(&self) -> Vec<Vec2> {
+        if self.mazing {
+            return Vec::new();
+        }
+        self.cells
+            .cells_of_type(CellType::Buildable)
+            .map(|cell| self.cell_center(cell))
+            .collect()
+    }
+}
+
+/// Every level of the loaded LDtk project and which one is currently being played
+#[derive(Resource)]
+pub struct Campaign {
+    pub source: String,
+    pub handle: Handle<LdtkProject>,
+    /// Copy of the project asset, empty until it has been loaded
+    pub root: Root,
+    pub current: usize,
+}
+
+impl Campaign {
+    pub fn new(source: &str, handle: Handle<LdtkProject>) -> Self {
+        Campaign {
+            source: source.to_string(),
+            handle,
+            root: Root::default(),
+            current: 0,
+        }
+    }
+
+    pub fn current_map(&self) -> Result<GameMap, MapLoadError> {
+        let level = self.root.levels.get(self.current).ok_or(MapLoadError::LevelNotFound(LevelSelector::Index(self.current)))?;
+        GameMap::from_level(level, &self.root.defs)
+    }
+
+    /// Make the selected level the active one and build its map
+    pub fn select(&mut self, selector: &LevelSelector) -> Result<GameMap, MapLoadError> {
+        match self.root.level_index(selector) {
+            None => Err(MapLoadError::LevelNotFound(selector.clone())),
+            Some(i) => {
+                let map = GameMap::from_level(&self.root.levels[i], &self.root.defs)?;
+                self.current = i;
+                Ok(map)
+            }
+        }
+    }
+
+    /// Moves on to the next level, returns None once the last level has been completed
+    pub fn next_level(&mut self) -> Option<Result<GameMap, MapLoadError>> {
+        if self.current + 1 >= self.root.levels.len() {
+            return None;
+        }
+        Some(self.select(&LevelSelector::Index(self.current + 1)))
+    }
+}
+
+/// Send this when the active level has been beaten
+pub struct LevelCompleteEvent;
+
+/// Sent after the level file changed on disk and the GameMap was rebuilt from it
+pub struct LevelReloadedEvent;
+
+/// Why the last map failed to load, shown on the main menu
+#[derive(Resource)]
+pub struct MapLoadErrorMessage(pub String);
+
+/// Anything tagged with this is torn down when we leave the level
+#[derive(Component)]
+pub struct LevelEntity;
+
 #[derive(Component)]
 pub struct GroundPlane;
 
+/// An empty slot a tower can be built on
+#[derive(Component)]
+pub struct TowerBase;
+
+/// Never drawn copy of the whole ground, clicked to pick the cell to build on when mazing
+#[derive(Component, Default)]
+pub struct GroundCollider {
+    /// Open cell that was clicked last, None when the click landed somewhere a tower can not go
+    pub picked: Option<UVec2>,
+}
+
+/// Shows which cell of the [GroundCollider] has been picked
+#[derive(Component)]
+pub struct PickedCellMarker;
+
+/// The [min, max) cells a piece of the ground mesh was built from
+#[derive(Component)]
+pub struct GroundChunk {
+    pub min: UVec2,
+    pub max: UVec2,
+}
+
+impl GroundChunk {
+    /// Walls depend on the neighbouring cells, so a chunk also cares about the ring of cells around it
+    fn is_affected_by(&self, cell: UVec2) -> bool {
+        cell.x + 1 >= self.min.x && cell.x <= self.max.x && cell.y + 1 >= self.min.y && cell.y <= self.max.y
+    }
+}
+
+/// Sent by [GameMap::set_cell_type] so the ground chunks around the cell get rebuilt
+pub struct GroundChangedEvent {
+    pub cell: UVec2,
+}
+
+/// Height of the (pickable) tower base capsule above the ground
+pub const TOWER_BASE_HEIGHT: f32 = 0.8;
+
+/// Shared handles for the empty, pickable tower slots
+#[derive(Resource, Clone)]
+pub struct TowerBaseAssets {
+    pub mesh: Handle<Mesh>,
+    pub default_material: Handle<StandardMaterial>,
+    pub selected_material: Handle<StandardMaterial>,
+    pub pedestal: Handle<Scene>,
+}
+
+pub fn spawn_tower_base(
+    commands: &mut Commands,
+    tower_base: &TowerBaseAssets,
+    position: Vec3,
+) -> Entity {
+    // Create an empty, to store our children
+    commands.spawn(SpatialBundle::from_transform(
+        Transform::from_translation(position)
+    ))
+        .insert((Name::new("Tower_base"), TowerBase, LevelEntity))
+        .insert(tower_base.mesh.clone())
+        .insert(NotShadowCaster)
+        .insert(PickableBundle::default())
+        .insert(Highlighting {
+            initial: tower_base.default_material.clone(),
+            hovered: Some(tower_base.selected_material.clone()),
+            pressed: Some(tower_base.selected_material.clone()),
+            selected: Some(tower_base.selected_material.clone()),
+        })
+        .insert(tower_base.default_material.clone())
+        .with_children(|commands| {
+            // Tower pedestal
+            commands.spawn(SceneBundle {
+                scene: tower_base.pedestal.clone(),
+                transform: Transform::from_xyz(0.0, -0.9, 0.0),
+                ..default()
+            })
+                .insert(Name::new("Pedestal"));
+        }).id()
+}
+
 fn load_assets(
-    mut commands: Commands
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
 ) {
     info!("Loading the map...");
-    let map = match GameMap::load("./assets/levels/test.ldtk") {
-        Ok(v) => v,
+    commands.insert_resource(Campaign::new(CAMPAIGN_FILE, asset_server.load(CAMPAIGN_FILE)));
+}
+
+fn wait_for_campaign(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    projects: Res<Assets<LdtkProject>>,
+    mut campaign: ResMut<Campaign>,
+    mut game_state: ResMut<State<GameState>>,
+) {
+    let project = match projects.get(&campaign.handle) {
+        Some(project) => project,
+        None => {
+            if asset_server.get_load_state(&campaign.handle) == LoadState::Failed {
+                error!("Failed to load game map {}!", CAMPAIGN_FILE);
+                commands.insert_resource(MapLoadErrorMessage(format!("Failed to load {}, see the log for details", CAMPAIGN_FILE)));
+                game_state.set(GameState::MainMenu).unwrap();
+            }
+            return;
+        }
+    };
+
+    campaign.root = project.root.clone();
+    match campaign.current_map() {
+        Ok(map) => {
+            info!("Campaign levels: {:?}", campaign.root.level_identifiers());
+            commands.insert_resource(map);
+            game_state.set(GameState::Gameplay).unwrap();
+        }
+        Err(e) => {
+            error!("Failed to load game map! {}", e);
+            commands.insert_resource(MapLoadErrorMessage(format!("Failed to load {}: {}", CAMPAIGN_FILE, e)));
+            game_state.set(GameState::MainMenu).unwrap();
+        }
+    }
+}
+
+/// Picks up changes to the level file and rebuilds the GameMap of the active level from it
+pub fn reload_campaign(
+    mut commands: Commands,
+    mut project_events: EventReader<AssetEvent<LdtkProject>>,
+    projects: Res<Assets<LdtkProject>>,
+    mut campaign: ResMut<Campaign>,
+    map: Option<ResMut<GameMap>>,
+    mut level_reloaded: EventWriter<LevelReloadedEvent>,
+) {
+    let modified = project_events
+        .iter()
+        .any(|e| matches!(e, AssetEvent::Modified { handle } if *handle == campaign.handle));
+    let project = match projects.get(&campaign.handle) {
+        Some(project) if modified => project,
+        _ => return,
+    };
+
+    info!("{} changed, reloading level", campaign.source);
+    campaign.root = project.root.clone();
+    let mut new_map = match campaign.current_map() {
+        Ok(new_map) => new_map,
         Err(e) => {
-            warn!("Failed to load game map! {}", e);
+            // Keep playing the old version until the file is fixed
+            error!("Failed to reload game map! {}", e);
             return;
         }
     };
 
-    //TODO: Show map load error dialog and push to main menu
+    match map {
+        Some(mut map) => {
+            // Towers that have been built keep blocking their cells
+            for cell in map.blocked.keys() {
+                if let Some(cell_type) = new_map.cells.set(cell.x as usize, cell.y as usize, CellType::Buildable) {
+                    new_map.blocked.insert(*cell, cell_type);
+                }
+            }
+            new_map.rebuild_flow_field();
+            *map = new_map;
+            level_reloaded.send(LevelReloadedEvent);
+        }
+        None => {
+            commands.remove_resource::<MapLoadErrorMessage>();
+            commands.insert_resource(new_map);
+        }
+    }
+}
 
-    commands.insert_resource(map);
+/// Swaps the ground and the empty tower slots of a running level for ones built from the reloaded map
+#[allow(clippy::too_many_arguments)]
+fn rebuild_level_scene(
+    mut commands: Commands,
+    mut level_reloaded: EventReader<LevelReloadedEvent>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    tower_base: Option<Res<TowerBaseAssets>>,
+    map: Res<GameMap>,
+    grounds: Query<Entity, With<GroundPlane>>,
+    tower_bases: Query<Entity, With<TowerBase>>,
+    towers: Query<&GlobalTransform, With<Tower>>,
+) {
+    if level_reloaded.iter().last().is_none() {
+        return;
+    }
+    // Only a level that is being played has a ground to replace
+    let tower_base = match tower_base {
+        Some(tower_base) if !grounds.is_empty() => tower_base,
+        _ => return,
+    };
+
+    for entity in grounds.iter().chain(tower_bases.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let map = map.into_inner();
+    spawn_ground(&mut commands, &mut meshes, &mut materials, &tower_base, map);
+
+    let built: HashSet<UVec2> = towers
+        .iter()
+        .filter_map(|t| map.world_to_cell(t.translation().xz()))
+        .collect();
+    for slot in map.tower_slots() {
+        if !map.world_to_cell(slot).is_some_and(|cell| built.contains(&cell)) {
+            spawn_tower_base(&mut commands, &tower_base, slot.extend(map.height_at(slot) + TOWER_BASE_HEIGHT).xzy());
+        }
+    }
+}
+
+fn advance_level(
+    mut commands: Commands,
+    mut level_complete: EventReader<LevelCompleteEvent>,
+    mut victory: EventWriter<VictoryEvent>,
+    mut campaign: ResMut<Campaign>,
+    mut map: ResMut<GameMap>,
+    mut game_state: ResMut<State<GameState>>,
+) {
+    if level_complete.iter().last().is_none() {
+        return;
+    }
+
+    match campaign.next_level() {
+        Some(Ok(next_map)) => {
+            info!("Level {} complete, moving on to {}", map.name, next_map.name);
+            *map = next_map;
+            // Restarting the state runs on_exit and on_enter, tearing down and rebuilding the level
+            game_state.restart().unwrap();
+        }
+        Some(Err(e)) => {
+            error!("Failed to load next level! {}", e);
+            commands.insert_resource(MapLoadErrorMessage(format!("Failed to load next level: {}", e)));
+            game_state.set(GameState::MainMenu).unwrap();
+        }
+        None => {
+            info!("Campaign complete!");
+            // Back to the first level for the next run, the victory screen builds its map when leaving
+            campaign.current = 0;
+            victory.send(VictoryEvent);
+        }
+    }
+}
+
+fn rebuild_ground_chunks(
+    mut ground_changed: EventReader<GroundChangedEvent>,
+    chunks: Query<(&GroundChunk, &Handle<Mesh>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    map: Res<GameMap>,
+) {
+    let changed: Vec<UVec2> = ground_changed.iter().map(|e| e.cell).collect();
+    if changed.is_empty() {
+        return;
+    }
+
+    for (chunk, mesh) in &chunks {
+        if changed.iter().any(|cell| chunk.is_affected_by(*cell)) {
+            if let Some(mesh) = meshes.get_mut(mesh) {
+                *mesh = ground_mesh(&map, chunk.min, chunk.max);
+            }
+        }
+    }
+}
+
+fn despawn_level(
+    mut commands: Commands,
+    entities: Query<Entity, With<LevelEntity>>,
+) {
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
 }
 
 //pbr bundle - Physically base rendering
@@ -60,73 +555,24 @@ fn spawn_basic_scene(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
-    assets: Res<AssetServer>,
+    assets: Res<GameAssets>,
     map: Res<GameMap>,
 ) {
-    let default_collider_color: Handle<StandardMaterial> = materials.add(
-        Color::rgba(0.3, 0.3, 0.3, 0.3).into()
-    );
-    let selected_collider_color: Handle<StandardMaterial> = materials.add(
-        Color::rgba(0.3, 0.9, 0.3, 0.9).into()
-    );
+    let tower_base = TowerBaseAssets {
+        mesh: meshes.add(shape::Capsule::default().into()),
+        default_material: materials.add(Color::rgba(0.3, 0.3, 0.3, 0.3).into()),
+        selected_material: materials.add(Color::rgba(0.3, 0.9, 0.3, 0.9).into()),
+        pedestal: assets.pedestal.clone(),
+    };
 
     let map = map.into_inner();
+    spawn_ground(&mut commands, &mut meshes, &mut materials, &tower_base, map);
 
-    // Ground plane
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(Mesh::from(map)),
-        material: materials.add(Color::rgb(0.67, 0.84, 0.52).into()),
-        ..default()
-    }).insert((Name::new("Ground"), GroundPlane, Wireframe));
-
-    // Create an empty, to store our children
-    commands.spawn(SpatialBundle::from_transform(
-        Transform::from_xyz(0.0, 0.8, 0.0)
-    ))
-        .insert(Name::new("Tower_base"))
-        .insert(meshes.add(shape::Capsule::default().into()))
-        .insert(NotShadowCaster)
-        .insert(PickableBundle::default())
-        .insert(Highlighting {
-            initial: default_collider_color.clone(),
-            hovered: Some(selected_collider_color.clone()),
-            pressed: Some(selected_collider_color.clone()),
-            selected: Some(selected_collider_color.clone()),
-        })
-        .insert(default_collider_color.clone())
-        .with_children(|commands| {
-            // Tower pedestal
-            commands.spawn(SceneBundle {
-                scene: assets.load("models/pedestal.glb#Scene0"),
-                transform: Transform::from_xyz(0.0, -0.9, 0.0),
-                ..default()
-            })
-                .insert(Name::new("Pedestal"));
-        });
-
-    commands.spawn(SpatialBundle::from_transform(
-        Transform::from_xyz(1.5, 0.8, 0.0)
-    ))
-        .insert(Name::new("Tower_base"))
-        .insert(meshes.add(shape::Capsule::default().into()))
-        .insert(NotShadowCaster)
-        .insert(PickableBundle::default())
-        .insert(Highlighting {
-            initial: default_collider_color.clone(),
-            hovered: Some(selected_collider_color.clone()),
-            pressed: Some(selected_collider_color.clone()),
-            selected: Some(selected_collider_color),
-        })
-        .insert(default_collider_color)
-        .with_children(|commands| {
-            // Tower pedestal
-            commands.spawn(SceneBundle {
-                scene: assets.load("models/pedestal.glb#Scene0"),
-                transform: Transform::from_xyz(0.0, -0.9, 0.0),
-                ..default()
-            })
-                .insert(Name::new("Pedestal"));
-        });
+    // Level designers decide where towers go by painting buildable cells
+    for slot in map.tower_slots() {
+        spawn_tower_base(&mut commands, &tower_base, slot.extend(map.height_at(slot) + TOWER_BASE_HEIGHT).xzy());
+    }
+    commands.insert_resource(tower_base);
 
     // Light
     commands.spawn(PointLightBundle {
@@ -137,5 +583,169 @@ fn spawn_basic_scene(
         },
         transform: Transform::from_xyz(10.0, 8.0, 4.0),
         ..default()
-    }).insert(Name::new("Light"));
-}
\ No newline at end of file
+    }).insert((Name::new("Light"), LevelEntity));
+}
+
+/// Ground plane, split in chunks and coloured per cell through its vertex colours
+fn spawn_ground(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    tower_base: &TowerBaseAssets,
+    map: &GameMap,
+) {
+    let ground_material = materials.add(Color::WHITE.into());
+    commands.spawn(SpatialBundle::default())
+        .insert((Name::new("Ground"), GroundPlane, LevelEntity))
+        .with_children(|commands| {
+            for (min, max) in ground_chunks(map) {
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(ground_mesh(map, min, max)),
+                    material: ground_material.clone(),
+                    ..default()
+                }).insert((Name::new(format!("Ground_chunk_{}_{}", min.x, min.y)), GroundChunk { min, max }, Wireframe));
+            }
+
+            if map.mazing {
+                // Only there to be picked, without a material it is never drawn
+                commands.spawn(SpatialBundle::default())
+                    .insert((Name::new("Ground_collider"), GroundCollider::default()))
+                    .insert(meshes.add(Mesh::from(map)))
+                    .insert(PickableBundle::default());
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(shape::Plane { size: map.grid_size as f32 }.into()),
+                    material: tower_base.selected_material.clone(),
+                    visibility: Visibility::INVISIBLE,
+                    ..default()
+                }).insert((Name::new("Picked_cell"), PickedCellMarker, NotShadowCaster));
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::event::Events;
+    use bevy::ecs::system::SystemState;
+    use super::*;
+    use crate::helpers::PathNode;
+
+    /// Open ground of `width` by `height` cells of size 1
+    fn open_map(width: usize, height: usize) -> GameMap {
+        GameMap {
+            grid_size: 1,
+            cells: CellGrid {
+                width,
+                height,
+                cells: vec![CellType::Empty; width * height],
+                ..default()
+            },
+            ..default()
+        }
+    }
+
+    /// Makes the edits with a fresh event writer and returns the cells of the events they sent
+    fn edit(map: &mut GameMap, edits: impl FnOnce(&mut GameMap, &mut EventWriter<GroundChangedEvent>)) -> Vec<UVec2> {
+        let mut world = World::new();
+        world.init_resource::<Events<GroundChangedEvent>>();
+        let mut state: SystemState<EventWriter<GroundChangedEvent>> = SystemState::new(&mut world);
+        edits(map, &mut state.get_mut(&mut world));
+
+        let events = world.resource::<Events<GroundChangedEvent>>();
+        let cells = events.get_reader().iter(events).map(|e| e.cell).collect();
+        cells
+    }
+
+    /// How many ground chunks the events of a single cell edit get rebuilt
+    fn chunks_marked(map: &mut GameMap, cell: UVec2) -> usize {
+        let changed = edit(map, |map, ground_changed| {
+            map.set_cell_type(cell, CellType::Raised, ground_changed);
+        });
+        assert_eq!(changed, vec![cell]);
+        ground_chunks(map)
+            .into_iter()
+            .map(|(min, max)| GroundChunk { min, max })
+            .filter(|chunk| chunk.is_affected_by(cell))
+            .count()
+    }
+
+    #[test]
+    fn a_cell_edit_marks_the_chunk_it_is_in() {
+        let mut map = open_map(40, 20);
+        assert_eq!(chunks_marked(&mut map, UVec2::new(5, 5)), 1);
+        assert_eq!(chunks_marked(&mut map, UVec2::new(20, 17)), 1);
+    }
+
+    #[test]
+    fn a_cell_edit_on_a_chunk_border_marks_both_chunks() {
+        let mut map = open_map(40, 20);
+        assert_eq!(chunks_marked(&mut map, UVec2::new(15, 5)), 2);
+        assert_eq!(chunks_marked(&mut map, UVec2::new(16, 5)), 2);
+        assert_eq!(chunks_marked(&mut map, UVec2::new(16, 16)), 4);
+    }
+
+    #[test]
+    fn setting_a_cell_to_what_it_already_is_changes_nothing() {
+        let mut map = open_map(4, 4);
+        let changed = edit(&mut map, |map, ground_changed| {
+            assert!(!map.set_cell_type(UVec2::new(1, 1), CellType::Empty, ground_changed));
+            assert!(!map.set_cell_type(UVec2::new(9, 1), CellType::Raised, ground_changed));
+        });
+        assert!(changed.is_empty());
+    }
+
+    #[test]
+    fn building_while_mazing_paints_the_cell_until_the_tower_is_sold() {
+        // Spawn in the top left, Finish in the top right of a 3 by 2 field
+        let mut map = open_map(3, 2);
+        map.mazing = true;
+        map.paths.nodes = vec![PathNode::new(Vec2::new(0.5, 0.5)), PathNode::finish(Vec2::new(2.5, 0.5))];
+        map.paths.spawns = vec![0];
+        map.paths.connect(0, 1);
+        map.rebuild_flow_field();
+        let cell = UVec2::new(1, 1);
+
+        let changed = edit(&mut map, |map, ground_changed| assert!(map.try_block_cell(cell, &[], ground_changed)));
+        assert_eq!(changed, vec![cell]);
+        assert_eq!(map.cells.get(1, 1), Some(CellType::Buildable));
+        assert_eq!(map.flow_field.distance(cell), None);
+
+        let changed = edit(&mut map, |map, ground_changed| map.unblock_cell(cell, ground_changed));
+        assert_eq!(changed, vec![cell]);
+        assert_eq!(map.cells.get(1, 1), Some(CellType::Empty));
+        assert!(map.flow_field.distance(cell).is_some());
+    }
+
+    #[test]
+    fn a_tower_that_would_wall_off_the_finish_leaves_the_ground_alone() {
+        let mut map = open_map(3, 1);
+        map.mazing = true;
+        map.paths.nodes = vec![PathNode::new(Vec2::new(0.5, 0.5)), PathNode::finish(Vec2::new(2.5, 0.5))];
+        map.paths.spawns = vec![0];
+        map.paths.connect(0, 1);
+        map.rebuild_flow_field();
+
+        let changed = edit(&mut map, |map, ground_changed| assert!(!map.try_block_cell(UVec2::new(1, 0), &[], ground_changed)));
+        assert!(changed.is_empty());
+        assert_eq!(map.cells.get(1, 0), Some(CellType::Empty));
+        assert!(map.blocked.is_empty());
+    }
+
+    #[test]
+    fn mazing_builds_on_picked_open_ground_instead_of_bases() {
+        let mut map = open_map(3, 1);
+        map.cells.cells = vec![CellType::Path, CellType::Buildable, CellType::Raised];
+        map.paths.nodes = vec![PathNode::new(Vec2::new(0.5, 0.5))];
+        map.paths.spawns = vec![0];
+        assert_eq!(map.tower_slots(), vec![Vec2::new(1.5, 0.5)]);
+        assert!(!map.is_tower_slot(UVec2::new(0, 0)));
+        assert!(map.is_tower_slot(UVec2::new(1, 0)));
+
+        map.mazing = true;
+        assert!(map.tower_slots().is_empty());
+        // Not on the Spawn, nor on raised ground
+        assert!(!map.is_tower_slot(UVec2::new(0, 0)));
+        assert!(map.is_tower_slot(UVec2::new(1, 0)));
+        assert!(!map.is_tower_slot(UVec2::new(2, 0)));
+        assert!(!map.is_tower_slot(UVec2::new(3, 0)));
+    }
+}
@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use crate::helpers::{IntGridValue, LayerInstance};
+
+/// How far "raised" cells stick out of the ground
+pub const RAISED_CELL_HEIGHT: f32 = 0.5;
+
+/// Colour of cells that have no IntGrid value painted
+pub const EMPTY_CELL_COLOR: Color = Color::rgb(0.67, 0.84, 0.52);
+
+/// What a single IntGrid cell is used for, resolved from the IntGrid value identifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CellType {
+    #[default]
+    Empty,
+    Path,
+    Raised,
+    Buildable,
+    /// A value the game does not know about yet
+    Other(i64),
+}
+
+impl CellType {
+    pub fn from_identifier(identifier: &str, value: i64) -> Self {
+        match identifier {
+            "path" => CellType::Path,
+            "raised" => CellType::Raised,
+            "buildable" => CellType::Buildable,
+            _ => CellType::Other(value),
+        }
+    }
+
+    /// Height of the top of the cell above y=0
+    pub fn height(&self) -> f32 {
+        match self {
+            CellType::Raised => RAISED_CELL_HEIGHT,
+            _ => 0.0,
+        }
+    }
+}
+
+/// The IntGrid layer of a level, row major with (0, 0) in the top left corner
+#[derive(Debug, Clone, Default)]
+pub struct CellGrid {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<CellType>,
+    /// Editor colour of every IntGrid value
+    pub palette: HashMap<CellType, Color>,
+}
+
+impl CellGrid {
+    pub fn from_layer(layer: &LayerInstance, values: &[IntGridValue]) -> Self {
+        let cells = layer.int_grid_csv
+            .iter()
+            .map(|&v| match values.iter().find(|iv| iv.value == v) {
+                None if v == 0 => CellType::Empty,
+                None => CellType::Other(v),
+                Some(iv) => CellType::from_identifier(&iv.identifier, v),
+            })
+            .collect();
+
+        let palette = values
+            .iter()
+            .filter_map(|iv| {
+                let color = Color::hex(iv.color.trim_start_matches('#')).ok()?;
+                Some((CellType::from_identifier(&iv.identifier, iv.value), color))
+            })
+            .collect();
+
+        CellGrid {
+            width: layer.c_wid as usize,
+            height: layer.c_hei as usize,
+            cells,
+            palette,
+        }
+    }
+
+    pub fn color(&self, cell_type: CellType) -> Color {
+        match cell_type {
+            CellType::Empty => EMPTY_CELL_COLOR,
+            _ => self.palette.get(&cell_type).copied().unwrap_or(EMPTY_CELL_COLOR),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<CellType> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells.get(y * self.width + x).copied()
+    }
+
+    /// Changes a cell, returning what it was before or None when it is outside the grid
+    pub fn set(&mut self, x: usize, y: usize, cell_type: CellType) -> Option<CellType> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.cells.get_mut(y * self.width + x).map(|c| std::mem::replace(c, cell_type))
+    }
+
+    /// Every cell with its grid coordinates
+    pub fn iter(&self) -> impl Iterator<Item=(UVec2, CellType)> + '_ {
+        let width = self.width.max(1);
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(i, c)| (UVec2::new((i % width) as u32, (i / width) as u32), *c))
+    }
+
+    pub fn cells_of_type(&self, cell_type: CellType) -> impl Iterator<Item=UVec2> + '_ {
+        self.iter().filter(move |(_, c)| *c == cell_type).map(|(pos, _)| pos)
+    }
+}
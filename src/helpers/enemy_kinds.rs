@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::fs;
+use serde_derive::{Deserialize, Serialize};
+use crate::damage::Resistances;
+
+/// Kind used when a wave asks for one the registry does not know
+pub const DEFAULT_ENEMY_KIND: &str = "basic";
+
+/// Stats of a kind of enemy as written in the enemy data file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnemyKindDef {
+    pub max_health: f32,
+    /// Taken off every physical hit, see [crate::damage::resolve_damage]
+    pub armour: f32,
+    pub resistances: Resistances,
+    /// World units per second
+    pub speed: f32,
+    /// Funds paid out for a kill
+    pub bounty: u32,
+    /// Lives lost when it reaches a Finish
+    pub lives_cost: u32,
+    /// Size of the hit box, scaled along with the model
+    pub collider: [f32; 3],
+    /// Asset path of the scene to show
+    pub model: String,
+    pub scale: f32,
+}
+
+impl Default for EnemyKindDef {
+    fn default() -> Self {
+        EnemyKindDef {
+            max_health: 4.0,
+            armour: 0.0,
+            resistances: Resistances::default(),
+            speed: 1.4,
+            bounty: 1,
+            lives_cost: 1,
+            collider: [0.24, 0.24, 0.1],
+            model: "models/enemy.glb#Scene0".to_string(),
+            scale: 1.0,
+        }
+    }
+}
+
+/// Reads the `{ "kind name": { stats } }` map of an enemy data file
+pub fn enemy_kinds_from_file(path: &str) -> Result<HashMap<String, EnemyKindDef>, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    serde_json::from_str(&data).map_err(|e| format!("could not parse {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameplay::GameMap;
+    use crate::helpers::Root;
+
+    fn shipped_kinds() -> HashMap<String, EnemyKindDef> {
+        enemy_kinds_from_file(&format!("{}/assets/data/enemies.json", env!("CARGO_MANIFEST_DIR"))).unwrap()
+    }
+
+    #[test]
+    fn loads_the_game_enemies() {
+        let kinds = shipped_kinds();
+        assert!(kinds.contains_key(DEFAULT_ENEMY_KIND));
+        let (scout, tank) = (&kinds["scout"], &kinds["tank"]);
+        assert!(scout.speed > tank.speed);
+        assert!(tank.bounty > scout.bounty);
+        assert!(tank.lives_cost > scout.lives_cost);
+        assert_eq!(tank.resistances.physical, 0.25);
+    }
+
+    #[test]
+    fn game_levels_only_send_known_enemies() {
+        let kinds = shipped_kinds();
+        let root = Root::from_file(&format!("{}/assets/levels/test.ldtk", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        for level in &root.levels {
+            let map = GameMap::from_level(level, &root.defs).unwrap();
+            for group in map.waves.waves.iter().flat_map(|w| &w.groups) {
+                assert!(kinds.contains_key(&group.enemy), "{} sends unknown enemy {}", level.identifier, group.enemy);
+            }
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use bevy::prelude::IVec2;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::helpers::{FieldInstance, MapLoadError, TileRect};
+
+/// Where an EntityRef field points
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityRef {
+    pub entity_iid: String,
+    pub layer_iid: String,
+    pub level_iid: String,
+    pub world_iid: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct GridPoint {
+    cx: i32,
+    cy: i32,
+}
+
+/// The `__value` of a level or entity field, resolved using its `__type`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    /// A field without a value, LDtk allows that for every type
+    Null,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// String and Multilines fields
+    String(String),
+    /// Hex colour, e.g. "#FF0000"
+    Color(String),
+    FilePath(String),
+    Enum { enum_type: String, value: String },
+    /// Grid cell
+    Point(IVec2),
+    EntityRef(EntityRef),
+    Tile(TileRect),
+    Array(Vec<FieldValue>),
+}
+
+impl FieldValue {
+    /// Parses a raw `__value` of the given LDtk `__type`, e.g. "Int", "LocalEnum.TowerKind" or "Array<Point>"
+    pub fn from_ldtk(field_type: &str, value: &Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(FieldValue::Null);
+        }
+        let mismatch = || format!("{} is not a valid {}", value, field_type);
+
+        if let Some(item_type) = field_type.strip_prefix("Array<").and_then(|t| t.strip_suffix('>')) {
+            let items = value.as_array().ok_or_else(mismatch)?;
+            return items
+                .iter()
+                .map(|item| FieldValue::from_ldtk(item_type, item))
+                .collect::<Result<Vec<_>, _>>()
+                .map(FieldValue::Array);
+        }
+        if let Some(enum_type) = field_type.strip_prefix("LocalEnum.").or_else(|| field_type.strip_prefix("ExternEnum.")) {
+            return Ok(FieldValue::Enum {
+                enum_type: enum_type.to_string(),
+                value: value.as_str().ok_or_else(mismatch)?.to_string(),
+            });
+        }
+
+        let string = || value.as_str().map(str::to_string).ok_or_else(mismatch);
+        match field_type {
+            "Int" => value.as_i64().map(FieldValue::Int).ok_or_else(mismatch),
+            "Float" => value.as_f64().map(FieldValue::Float).ok_or_else(mismatch),
+            "Bool" => value.as_bool().map(FieldValue::Bool).ok_or_else(mismatch),
+            "String" | "Multilines" => string().map(FieldValue::String),
+            "Color" => string().map(FieldValue::Color),
+            "FilePath" => string().map(FieldValue::FilePath),
+            "Point" => serde_json::from_value::<GridPoint>(value.clone())
+                .map(|p| FieldValue::Point(IVec2::new(p.cx, p.cy)))
+                .map_err(|_| mismatch()),
+            "EntityRef" => serde_json::from_value(value.clone()).map(FieldValue::EntityRef).map_err(|_| mismatch()),
+            "Tile" => serde_json::from_value(value.clone()).map(FieldValue::Tile).map_err(|_| mismatch()),
+            _ => Err(format!("unknown field type {}", field_type)),
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            FieldValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Int fields are fine where a Float is expected
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            FieldValue::Float(f) => Some(*f),
+            FieldValue::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            FieldValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Text of String fields and the value of Enum fields
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            FieldValue::String(s) | FieldValue::Enum { value: s, .. } => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Typed access to the fields of anything LDtk lets designers put fields on.
+/// A missing or empty field is `Ok(None)`, a field of the wrong type is an error.
+pub trait Fields {
+    fn field_instances(&self) -> &[FieldInstance];
+
+    /// Identifier of the level or entity, to point at the culprit in errors
+    fn owner(&self) -> &str;
+
+    fn malformed(&self, identifier: &str, reason: String) -> MapLoadError {
+        MapLoadError::MalformedField {
+            field: format!("{}.{}", self.owner(), identifier),
+            reason,
+        }
+    }
+
+    fn has_field(&self, identifier: &str) -> bool {
+        self.field_instances().iter().any(|f| f.identifier == identifier)
+    }
+
+    fn get(&self, identifier: &str) -> Result<Option<FieldValue>, MapLoadError> {
+        match self.field_instances().iter().find(|f| f.identifier == identifier) {
+            None => Ok(None),
+            Some(f) => match FieldValue::from_ldtk(&f.type_field, &f.value) {
+                Ok(FieldValue::Null) => Ok(None),
+                Ok(value) => Ok(Some(value)),
+                Err(reason) => Err(self.malformed(identifier, reason)),
+            },
+        }
+    }
+
+    /// Looks a field up and converts it, failing with `expected` when it has another type
+    fn get_as<T>(&self, identifier: &str, expected: &str, convert: impl FnOnce(&FieldValue) -> Option<T>) -> Result<Option<T>, MapLoadError> {
+        match self.get(identifier)? {
+            None => Ok(None),
+            Some(value) => convert(&value)
+                .map(Some)
+                .ok_or_else(|| self.malformed(identifier, format!("{:?} is not {}", value, expected))),
+        }
+    }
+
+    fn get_i64(&self, identifier: &str) -> Result<Option<i64>, MapLoadError> {
+        self.get_as(identifier, "a whole number", FieldValue::as_i64)
+    }
+
+    fn get_u32(&self, identifier: &str) -> Result<Option<u32>, MapLoadError> {
+        self.get_as(identifier, "a positive whole number", |v| v.as_i64().and_then(|i| u32::try_from(i).ok()))
+    }
+
+    fn get_f32(&self, identifier: &str) -> Result<Option<f32>, MapLoadError> {
+        self.get_as(identifier, "a number", |v| v.as_f64().map(|f| f as f32))
+    }
+
+    fn get_bool(&self, identifier: &str) -> Result<Option<bool>, MapLoadError> {
+        self.get_as(identifier, "a boolean", FieldValue::as_bool)
+    }
+
+    fn get_string(&self, identifier: &str) -> Result<Option<String>, MapLoadError> {
+        self.get_as(identifier, "a string", |v| match v {
+            FieldValue::String(s) => Some(s.clone()),
+            _ => None,
+        })
+    }
+
+    /// Value of an Enum field, e.g. "Cannon" for a `LocalEnum.TowerKind` field
+    fn get_enum(&self, identifier: &str) -> Result<Option<String>, MapLoadError> {
+        self.get_as(identifier, "an enum value", |v| match v {
+            FieldValue::Enum { value, .. } => Some(value.clone()),
+            _ => None,
+        })
+    }
+
+    fn get_point(&self, identifier: &str) -> Result<Option<IVec2>, MapLoadError> {
+        self.get_as(identifier, "a point", |v| match v {
+            FieldValue::Point(p) => Some(*p),
+            _ => None,
+        })
+    }
+
+    /// Every entity an EntityRef field points at, the field can hold a single reference or an array
+    fn get_entity_refs(&self, identifier: &str) -> Result<Vec<EntityRef>, MapLoadError> {
+        let refs = self.get_as(identifier, "an entity reference", |v| match v {
+            FieldValue::EntityRef(r) => Some(vec![r.clone()]),
+            FieldValue::Array(items) => items
+                .iter()
+                .map(|item| match item {
+                    FieldValue::EntityRef(r) => Some(r.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        })?;
+        Ok(refs.unwrap_or_default())
+    }
+
+    fn get_array(&self, identifier: &str) -> Result<Option<Vec<FieldValue>>, MapLoadError> {
+        self.get_as(identifier, "an array", |v| match v {
+            FieldValue::Array(items) => Some(items.clone()),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn entity_ref(iid: &str) -> Value {
+        json!({"entityIid": iid, "layerIid": "layer-1", "levelIid": "level-1", "worldIid": "world-1"})
+    }
+
+    #[test]
+    fn parses_numbers_and_booleans() {
+        assert_eq!(FieldValue::from_ldtk("Int", &json!(-3)), Ok(FieldValue::Int(-3)));
+        assert_eq!(FieldValue::from_ldtk("Float", &json!(0.5)), Ok(FieldValue::Float(0.5)));
+        // LDtk writes whole floats without a fraction
+        assert_eq!(FieldValue::from_ldtk("Float", &json!(2)), Ok(FieldValue::Float(2.0)));
+        assert_eq!(FieldValue::from_ldtk("Bool", &json!(true)), Ok(FieldValue::Bool(true)));
+    }
+
+    #[test]
+    fn parses_text() {
+        assert_eq!(FieldValue::from_ldtk("String", &json!("Hold the line!")), Ok(FieldValue::String("Hold the line!".to_string())));
+        assert_eq!(FieldValue::from_ldtk("Multilines", &json!("a\nb")), Ok(FieldValue::String("a\nb".to_string())));
+        assert_eq!(FieldValue::from_ldtk("Color", &json!("#FF0000")), Ok(FieldValue::Color("#FF0000".to_string())));
+        assert_eq!(
+            FieldValue::from_ldtk("LocalEnum.Fork", &json!("Shortest")),
+            Ok(FieldValue::Enum { enum_type: "Fork".to_string(), value: "Shortest".to_string() }),
+        );
+    }
+
+    #[test]
+    fn parses_points_and_entity_refs() {
+        assert_eq!(FieldValue::from_ldtk("Point", &json!({"cx": 2, "cy": 5})), Ok(FieldValue::Point(IVec2::new(2, 5))));
+        match FieldValue::from_ldtk("EntityRef", &entity_ref("finish-1")) {
+            Ok(FieldValue::EntityRef(r)) => assert_eq!(r.entity_iid, "finish-1"),
+            other => panic!("not an entity ref: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_arrays_item_by_item() {
+        assert_eq!(
+            FieldValue::from_ldtk("Array<Int>", &json!([1, 2])),
+            Ok(FieldValue::Array(vec![FieldValue::Int(1), FieldValue::Int(2)])),
+        );
+        let refs = FieldValue::from_ldtk("Array<EntityRef>", &json!([entity_ref("wp-1"), entity_ref("wp-2")])).unwrap();
+        assert!(matches!(&refs, FieldValue::Array(items) if items.len() == 2));
+        assert_eq!(FieldValue::from_ldtk("Array<Point>", &json!([])), Ok(FieldValue::Array(Vec::new())));
+        assert!(FieldValue::from_ldtk("Array<Int>", &json!([1, "two"])).is_err());
+        assert!(FieldValue::from_ldtk("Array<Int>", &json!(1)).is_err());
+    }
+
+    #[test]
+    fn empty_fields_are_null_whatever_their_type() {
+        assert_eq!(FieldValue::from_ldtk("Int", &Value::Null), Ok(FieldValue::Null));
+        assert_eq!(FieldValue::from_ldtk("EntityRef", &Value::Null), Ok(FieldValue::Null));
+    }
+
+    #[test]
+    fn refuses_values_that_do_not_match_their_type() {
+        assert_eq!(FieldValue::from_ldtk("Int", &json!("10")), Err("\"10\" is not a valid Int".to_string()));
+        assert!(FieldValue::from_ldtk("Int", &json!(1.5)).is_err());
+        assert!(FieldValue::from_ldtk("Float", &json!("0.5")).is_err());
+        assert!(FieldValue::from_ldtk("Bool", &json!(1)).is_err());
+        assert!(FieldValue::from_ldtk("String", &json!(3)).is_err());
+        assert!(FieldValue::from_ldtk("Point", &json!({"x": 2, "y": 5})).is_err());
+        assert!(FieldValue::from_ldtk("EntityRef", &json!("finish-1")).is_err());
+        assert!(FieldValue::from_ldtk("LocalEnum.Fork", &json!(0)).is_err());
+    }
+
+    #[test]
+    fn refuses_unknown_types() {
+        assert_eq!(FieldValue::from_ldtk("Vector", &json!(1)), Err("unknown field type Vector".to_string()));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+
+const NEIGHBOURS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+/// Walking distance (in cells) from every cell to the closest goal.
+/// Every enemy follows the same field, so it only has to be rebuilt when the walkable cells change.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowField {
+    pub width: usize,
+    pub height: usize,
+    /// None for cells that can not reach a goal
+    pub distance: Vec<Option<u32>>,
+}
+
+impl FlowField {
+    /// Breadth first search outwards from the goals over the walkable cells
+    pub fn compute(width: usize, height: usize, goals: &[UVec2], walkable: impl Fn(UVec2) -> bool) -> Self {
+        let mut field = FlowField {
+            width,
+            height,
+            distance: vec![None; width * height],
+        };
+
+        let mut open = VecDeque::new();
+        for &goal in goals {
+            if let Some(i) = field.index(goal) {
+                field.distance[i] = Some(0);
+                open.push_back(goal);
+            }
+        }
+
+        while let Some(cell) = open.pop_front() {
+            let distance = field.distance[field.index(cell).unwrap()].unwrap();
+            let neighbours: Vec<UVec2> = field.neighbours(cell).collect();
+            for neighbour in neighbours {
+                let i = field.index(neighbour).unwrap();
+                if field.distance[i].is_none() && walkable(neighbour) {
+                    field.distance[i] = Some(distance + 1);
+                    open.push_back(neighbour);
+                }
+            }
+        }
+        field
+    }
+
+    fn index(&self, cell: UVec2) -> Option<usize> {
+        let (x, y) = (cell.x as usize, cell.y as usize);
+        (x < self.width && y < self.height).then_some(y * self.width + x)
+    }
+
+    fn neighbours(&self, cell: UVec2) -> impl Iterator<Item=UVec2> + '_ {
+        NEIGHBOURS
+            .iter()
+            .map(move |d| cell.as_ivec2() + *d)
+            .filter(|n| n.x >= 0 && n.y >= 0)
+            .map(|n| n.as_uvec2())
+            .filter(|n| self.index(*n).is_some())
+    }
+
+    pub fn distance(&self, cell: UVec2) -> Option<u32> {
+        self.index(cell).and_then(|i| self.distance[i])
+    }
+
+    pub fn is_goal(&self, cell: UVec2) -> bool {
+        self.distance(cell) == Some(0)
+    }
+
+    /// The neighbouring cell one step closer to a goal
+    pub fn next_cell(&self, cell: UVec2) -> Option<UVec2> {
+        let current = self.distance(cell)?;
+        self.neighbours(cell)
+            .filter_map(|n| self.distance(n).map(|d| (d, n)))
+            .filter(|(d, _)| *d < current)
+            .min_by_key(|(d, _)| *d)
+            .map(|(_, n)| n)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walkable everywhere except the cells marked `#`, rows top to bottom
+    fn field(rows: &[&str], goals: &[UVec2]) -> FlowField {
+        let blocked = |cell: UVec2| rows[cell.y as usize].as_bytes()[cell.x as usize] == b'#';
+        FlowField::compute(rows[0].len(), rows.len(), goals, |cell| !blocked(cell))
+    }
+
+    #[test]
+    fn distances_count_the_steps_to_the_goal() {
+        let field = field(&["....", "...."], &[UVec2::new(0, 0)]);
+        assert_eq!(field.distance(UVec2::new(0, 0)), Some(0));
+        assert_eq!(field.distance(UVec2::new(3, 0)), Some(3));
+        assert_eq!(field.distance(UVec2::new(3, 1)), Some(4));
+        assert!(field.is_goal(UVec2::new(0, 0)));
+    }
+
+    #[test]
+    fn the_closest_goal_wins() {
+        let field = field(&["....."], &[UVec2::new(0, 0), UVec2::new(4, 0)]);
+        assert_eq!(field.distance(UVec2::new(1, 0)), Some(1));
+        assert_eq!(field.distance(UVec2::new(3, 0)), Some(1));
+        assert_eq!(field.distance(UVec2::new(2, 0)), Some(2));
+    }
+
+    #[test]
+    fn walks_around_blocked_cells() {
+        let field = field(&[
+            ".#.",
+            ".#.",
+            "...",
+        ], &[UVec2::new(0, 0)]);
+        assert_eq!(field.distance(UVec2::new(1, 0)), None);
+        assert_eq!(field.distance(UVec2::new(2, 0)), Some(6));
+        assert_eq!(field.next_cell(UVec2::new(2, 0)), Some(UVec2::new(2, 1)));
+    }
+
+    #[test]
+    fn walled_off_cells_can_not_reach_a_goal() {
+        let field = field(&["..#.."], &[UVec2::new(0, 0)]);
+        assert_eq!(field.distance(UVec2::new(1, 0)), Some(1));
+        assert_eq!(field.distance(UVec2::new(3, 0)), None);
+        assert_eq!(field.distance(UVec2::new(4, 0)), None);
+        assert_eq!(field.next_cell(UVec2::new(4, 0)), None);
+    }
+
+    #[test]
+    fn cells_outside_the_field_have_no_distance() {
+        let field = field(&["..", ".."], &[UVec2::new(0, 0), UVec2::new(7, 7)]);
+        assert_eq!(field.distance(UVec2::new(2, 0)), None);
+        assert_eq!(field.distance(UVec2::new(1, 1)), Some(2));
+    }
+
+    #[test]
+    fn next_cell_steps_closer_until_the_goal() {
+        let field = field(&["...."], &[UVec2::new(3, 0)]);
+        assert_eq!(field.next_cell(UVec2::new(0, 0)), Some(UVec2::new(1, 0)));
+        assert_eq!(field.next_cell(UVec2::new(3, 0)), None);
+    }
+}
@@ -1,6 +1,13 @@
-use bevy::prelude::Mesh;
+use bevy::prelude::{Mesh, UVec2};
 use bevy::render::mesh::{Indices, PrimitiveTopology};
 use crate::gameplay::GameMap;
+use crate::helpers::CellType;
+
+/// Width and depth of a ground chunk in cells, editing a cell only rebuilds its own chunk
+pub const GROUND_CHUNK_SIZE: u32 = 16;
+
+/// Walls are drawn a bit darker than the top of a cell so raised blocks stand out
+const WALL_SHADE: f32 = 0.7;
 
 #[derive(Debug)]
 pub struct GameFieldGround {
@@ -8,60 +15,380 @@ pub struct GameFieldGround {
     pub height: f32,
 }
 
+/// Rectangle of cells of the same type merged into a single top face, in grid coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroundQuad {
+    pub min: UVec2,
+    pub size: UVec2,
+    pub cell_type: CellType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallSide {
+    PosX,
+    NegX,
+    PosZ,
+    NegZ,
+}
+
+/// Run of wall faces along one side of a line of cells
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WallQuad {
+    pub side: WallSide,
+    /// First cell of the run, the run continues along z for x walls and along x for z walls
+    pub start: UVec2,
+    pub length: u32,
+    pub low: f32,
+    pub high: f32,
+    pub cell_type: CellType,
+}
+
+type Vertex = ([f32; 3], [f32; 3], [f32; 2], [f32; 4]);
+
+/// Pushes a quad, corners have to be counter clockwise when looking at the face from the normal side
+fn push_quad(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, corners: [[f32; 3]; 4], normal: [f32; 3], uv_size: [f32; 2], color: [f32; 4]) {
+    let vert_index = vertices.len() as u32;
+    // UVs repeat once per cell so a texture does not stretch over merged quads
+    let uvs = [[0.0, uv_size[1]], [uv_size[0], uv_size[1]], [uv_size[0], 0.0], [0.0, 0.0]];
+    for i in 0..4 {
+        vertices.push((corners[i], normal, uvs[i], color));
+    }
+    for i in &[0, 1, 2, 0, 2, 3] {
+        indices.push(*i + vert_index);
+    }
+}
+
+fn cell_type_at(map: &GameMap, x: u32, y: u32) -> CellType {
+    map.cells.get(x as usize, y as usize).unwrap_or_default()
+}
+
+/// Terrain height of a cell, anything outside of the map counts as ground level
+fn height_at_cell(map: &GameMap, x: i64, y: i64) -> f32 {
+    if x < 0 || y < 0 {
+        return 0.0;
+    }
+    map.cell_height(UVec2::new(x as u32, y as u32))
+}
+
+/// Clamps a [min, max) region to the cells that actually exist
+fn clamp_region(map: &GameMap, min: UVec2, max: UVec2) -> (UVec2, UVec2) {
+    let size = UVec2::new(map.cells.width as u32, map.cells.height as u32);
+    let max = max.min(size);
+    (min.min(max), max)
+}
+
+/// Greedily merges the top faces of the [min, max) region into as few rectangles as possible.
+/// Every cell is covered by exactly one quad of its own type.
+pub fn greedy_top_quads(map: &GameMap, min: UVec2, max: UVec2) -> Vec<GroundQuad> {
+    let (min, max) = clamp_region(map, min, max);
+    let size = max - min;
+    let mut done = vec![false; (size.x * size.y) as usize];
+    let index = |x: u32, y: u32| ((y - min.y) * size.x + (x - min.x)) as usize;
+    let mut quads = Vec::new();
+
+    for y in min.y..max.y {
+        for x in min.x..max.x {
+            if done[index(x, y)] {
+                continue;
+            }
+            let cell_type = cell_type_at(map, x, y);
+
+            // Grow along x as far as the cell type holds...
+            let mut width = 1;
+            while x + width < max.x && !done[index(x + width, y)] && cell_type_at(map, x + width, y) == cell_type {
+                width += 1;
+            }
+
+            // ...then along y for as long as the whole row matches
+            let mut height = 1;
+            'grow: while y + height < max.y {
+                for dx in 0..width {
+                    if done[index(x + dx, y + height)] || cell_type_at(map, x + dx, y + height) != cell_type {
+                        break 'grow;
+                    }
+                }
+                height += 1;
+            }
+
+            for dy in 0..height {
+                for dx in 0..width {
+                    done[index(x + dx, y + dy)] = true;
+                }
+            }
+
+            quads.push(GroundQuad {
+                min: UVec2::new(x, y),
+                size: UVec2::new(width, height),
+                cell_type,
+            });
+        }
+    }
+    quads
+}
+
+/// Walls of the [min, max) region, neighbouring walls with the same height and type are merged into runs
+pub fn wall_quads(map: &GameMap, min: UVec2, max: UVec2) -> Vec<WallQuad> {
+    let (min, max) = clamp_region(map, min, max);
+    let mut walls = Vec::new();
+
+    for side in [WallSide::PosX, WallSide::NegX, WallSide::PosZ, WallSide::NegZ] {
+        // x walls run along z (one line per column), z walls run along x (one line per row)
+        let (lines, steps) = match side {
+            WallSide::PosX | WallSide::NegX => (min.x..max.x, min.y..max.y),
+            WallSide::PosZ | WallSide::NegZ => (min.y..max.y, min.x..max.x),
+        };
+        let (dx, dy): (i64, i64) = match side {
+            WallSide::PosX => (1, 0),
+            WallSide::NegX => (-1, 0),
+            WallSide::PosZ => (0, 1),
+            WallSide::NegZ => (0, -1),
+        };
+
+        for line in lines {
+            let mut run: Option<WallQuad> = None;
+            for step in steps.clone() {
+                let cell = match side {
+                    WallSide::PosX | WallSide::NegX => UVec2::new(line, step),
+                    WallSide::PosZ | WallSide::NegZ => UVec2::new(step, line),
+                };
+                let high = map.cell_height(cell);
+                let low = height_at_cell(map, cell.x as i64 + dx, cell.y as i64 + dy);
+                let cell_type = cell_type_at(map, cell.x, cell.y);
+
+                let wall = (low < high).then_some(WallQuad { side, start: cell, length: 1, low, high, cell_type });
+                match (&mut run, wall) {
+                    (Some(current), Some(wall)) if current.low == wall.low && current.high == wall.high && current.cell_type == wall.cell_type => {
+                        current.length += 1;
+                    }
+                    (_, wall) => {
+                        if let Some(finished) = run.take() {
+                            walls.push(finished);
+                        }
+                        run = wall;
+                    }
+                }
+            }
+            if let Some(finished) = run {
+                walls.push(finished);
+            }
+        }
+    }
+    walls
+}
+
+/// Builds the ground for the [min, max) cell region, used to build a single chunk
+pub fn ground_mesh(map: &GameMap, min: UVec2, max: UVec2) -> Mesh {
+    let cell_size = map.grid_size as f32; //TODO: bring that in from the editor
+
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+
+    for quad in greedy_top_quads(map, min, max) {
+        let h = quad.cell_type.height();
+        let color = map.cells.color(quad.cell_type).as_linear_rgba_f32();
+        let (x0, z0) = (quad.min.x as f32 * cell_size, quad.min.y as f32 * cell_size);
+        let (x1, z1) = ((quad.min.x + quad.size.x) as f32 * cell_size, (quad.min.y + quad.size.y) as f32 * cell_size);
+        push_quad(&mut vertices, &mut indices, [[x0, h, z0], [x0, h, z1], [x1, h, z1], [x1, h, z0]], [0.0, 1.0, 0.0], [quad.size.x as f32, quad.size.y as f32], color);
+    }
+
+    for wall in wall_quads(map, min, max) {
+        let color = (map.cells.color(wall.cell_type) * WALL_SHADE).as_linear_rgba_f32();
+        let (lo, hi) = (wall.low, wall.high);
+        let uv_size = [wall.length as f32, 1.0];
+        match wall.side {
+            WallSide::PosX | WallSide::NegX => {
+                let z0 = wall.start.y as f32 * cell_size;
+                let z1 = (wall.start.y + wall.length) as f32 * cell_size;
+                if wall.side == WallSide::PosX {
+                    let x = (wall.start.x + 1) as f32 * cell_size;
+                    push_quad(&mut vertices, &mut indices, [[x, lo, z1], [x, lo, z0], [x, hi, z0], [x, hi, z1]], [1.0, 0.0, 0.0], uv_size, color);
+                } else {
+                    let x = wall.start.x as f32 * cell_size;
+                    push_quad(&mut vertices, &mut indices, [[x, lo, z0], [x, lo, z1], [x, hi, z1], [x, hi, z0]], [-1.0, 0.0, 0.0], uv_size, color);
+                }
+            }
+            WallSide::PosZ | WallSide::NegZ => {
+                let x0 = wall.start.x as f32 * cell_size;
+                let x1 = (wall.start.x + wall.length) as f32 * cell_size;
+                if wall.side == WallSide::PosZ {
+                    let z = (wall.start.y + 1) as f32 * cell_size;
+                    push_quad(&mut vertices, &mut indices, [[x0, lo, z], [x1, lo, z], [x1, hi, z], [x0, hi, z]], [0.0, 0.0, 1.0], uv_size, color);
+                } else {
+                    let z = wall.start.y as f32 * cell_size;
+                    push_quad(&mut vertices, &mut indices, [[x1, lo, z], [x0, lo, z], [x0, hi, z], [x1, hi, z]], [0.0, 0.0, -1.0], uv_size, color);
+                }
+            }
+        }
+    }
+
+    let indices = Indices::U32(indices);
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    for (position, normal, uv, color) in &vertices {
+        positions.push(*position);
+        normals.push(*normal);
+        uvs.push(*uv);
+        colors.push(*color);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(indices));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh
+}
+
+/// [min, max) cell bounds of every ground chunk of the map
+pub fn ground_chunks(map: &GameMap) -> Vec<(UVec2, UVec2)> {
+    let size = UVec2::new(map.cells.width as u32, map.cells.height as u32);
+    let mut chunks = Vec::new();
+    for y in (0..size.y).step_by(GROUND_CHUNK_SIZE as usize) {
+        for x in (0..size.x).step_by(GROUND_CHUNK_SIZE as usize) {
+            let min = UVec2::new(x, y);
+            chunks.push((min, (min + UVec2::splat(GROUND_CHUNK_SIZE)).min(size)));
+        }
+    }
+    chunks
+}
+
 impl From<&GameMap> for Mesh {
     fn from(map: &GameMap) -> Self {
-        let cell_size = map.grid_size as f32; //TODO: bring that in from the editor
-
-        let mut vertices: Vec<([f32; 3], [f32; 3], [f32; 2])> = Vec::new();
-        let mut indices: Vec<u32> = Vec::new();
-
-        //let x_offset = map.width / 2.;
-        let x_offset = 0.;
-        //let y_offset = map.height / 2.;
-        let y_offset = 0.;
-
-        for x in (0..map.width as i32).rev() {
-            let pos_x = (x as f32 * cell_size) - x_offset;
-            for y in (0..map.height as i32).rev() {
-                let pos_y = (y as f32 * cell_size) - y_offset;
-                // each cell is made of 4 vertices and 2 triangles
-                // we are assuming the center of the plane will be 0,0
-                // Creating counter clockwise
-                let vert_index = vertices.len() as u32;
-
-                let loc_vertices = [
-                    ([pos_x + cell_size, 0.0, pos_y], [0.0, 1.0, 0.0], [1.0, 1.0]),
-                    ([pos_x + cell_size, 0.0, pos_y + cell_size], [0.0, 1.0, 0.0], [1.0, 0.0]),
-                    ([pos_x, 0.0, pos_y + cell_size], [0.0, 1.0, 0.0], [0.0, 0.0]),
-                    ([pos_x, 0.0, pos_y], [0.0, 1.0, 0.0], [0.0, 1.0]),
-                ];
-                vertices.push(loc_vertices[0]);
-                vertices.push(loc_vertices[1]);
-                vertices.push(loc_vertices[2]);
-                vertices.push(loc_vertices[3]);
-
-                for i in &[1, 0, 2, 3, 2, 0] {
-                    indices.push(*i + vert_index);
+        ground_mesh(map, UVec2::ZERO, UVec2::new(map.cells.width as u32, map.cells.height as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::CellGrid;
+
+    /// Builds a map from rows of IntGrid digits, 0 empty, 2 path, 3 raised, 4 buildable
+    fn map_from_rows(rows: &[&str]) -> GameMap {
+        let cells = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|c| match c {
+                '2' => CellType::Path,
+                '3' => CellType::Raised,
+                '4' => CellType::Buildable,
+                _ => CellType::Empty,
+            })
+            .collect();
+        GameMap {
+            width: rows[0].len() as f32,
+            height: rows.len() as f32,
+            grid_size: 2,
+            cells: CellGrid {
+                width: rows[0].len(),
+                height: rows.len(),
+                cells,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn whole_map(map: &GameMap) -> (UVec2, UVec2) {
+        (UVec2::ZERO, UVec2::new(map.cells.width as u32, map.cells.height as u32))
+    }
+
+    /// Every cell has to be covered by exactly one quad of its own type
+    fn assert_covers(map: &GameMap, quads: &[GroundQuad]) {
+        let mut covered = vec![0; map.cells.cells.len()];
+        for quad in quads {
+            for y in quad.min.y..quad.min.y + quad.size.y {
+                for x in quad.min.x..quad.min.x + quad.size.x {
+                    assert_eq!(map.cells.get(x as usize, y as usize), Some(quad.cell_type));
+                    covered[y as usize * map.cells.width + x as usize] += 1;
                 }
             }
         }
+        assert!(covered.iter().all(|&c| c == 1), "cells covered more or less than once: {:?}", covered);
+        let area: u32 = quads.iter().map(|q| q.size.x * q.size.y).sum();
+        assert_eq!(area as usize, map.cells.cells.len());
+    }
 
-        let indices = Indices::U32(indices);
+    #[test]
+    fn flat_map_is_a_single_quad() {
+        let map = map_from_rows(&["0000", "0000", "0000", "0000"]);
+        let (min, max) = whole_map(&map);
+        let quads = greedy_top_quads(&map, min, max);
+        assert_eq!(quads.len(), 1);
+        assert_covers(&map, &quads);
 
-        let mut positions = Vec::new();
-        let mut normals = Vec::new();
-        let mut uvs = Vec::new();
-        for (position, normal, uv) in &vertices {
-            positions.push(*position);
-            normals.push(*normal);
-            uvs.push(*uv);
-        }
+        let mesh = Mesh::from(&map);
+        assert_eq!(mesh.count_vertices(), 4);
+        assert_eq!(mesh.indices().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn checkerboard_cannot_be_merged() {
+        let map = map_from_rows(&["202", "020", "202"]);
+        let (min, max) = whole_map(&map);
+        let quads = greedy_top_quads(&map, min, max);
+        assert_eq!(quads.len(), 9);
+        assert_covers(&map, &quads);
+    }
+
+    #[test]
+    fn mixed_map_covers_same_area() {
+        let map = map_from_rows(&[
+            "33333333",
+            "32222443",
+            "32442443",
+            "32442223",
+            "33333333",
+        ]);
+        let (min, max) = whole_map(&map);
+        let quads = greedy_top_quads(&map, min, max);
+        assert_covers(&map, &quads);
+        assert!(quads.len() < map.cells.cells.len());
+    }
+
+    #[test]
+    fn raised_cell_gets_four_walls() {
+        let map = map_from_rows(&["000", "030", "000"]);
+        let (min, max) = whole_map(&map);
+        let quads = greedy_top_quads(&map, min, max);
+        let walls = wall_quads(&map, min, max);
+        assert_eq!(quads.len(), 5);
+        assert_eq!(walls.len(), 4);
+        assert!(walls.iter().all(|w| w.length == 1 && w.low == 0.0 && w.high == CellType::Raised.height()));
+
+        let mesh = Mesh::from(&map);
+        assert_eq!(mesh.count_vertices(), (quads.len() + walls.len()) * 4);
+        assert_eq!(mesh.indices().unwrap().len(), (quads.len() + walls.len()) * 6);
+    }
 
-        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-        mesh.set_indices(Some(indices));
-        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-        mesh
+    #[test]
+    fn wall_runs_are_merged() {
+        let map = map_from_rows(&["0000", "3330", "0000"]);
+        let (min, max) = whole_map(&map);
+        let walls = wall_quads(&map, min, max);
+        assert_eq!(walls.len(), 4);
+        let along_x: Vec<_> = walls.iter().filter(|w| matches!(w.side, WallSide::PosZ | WallSide::NegZ)).collect();
+        assert!(along_x.iter().all(|w| w.length == 3));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn chunks_cover_the_map() {
+        let rows: Vec<String> = (0..20).map(|y| (0..37).map(|x| if (x + y) % 5 == 0 { '2' } else { '4' }).collect()).collect();
+        let rows: Vec<&str> = rows.iter().map(|r| r.as_str()).collect();
+        let map = map_from_rows(&rows);
+
+        let chunks = ground_chunks(&map);
+        assert_eq!(chunks.len(), 3 * 2);
+
+        let quads: Vec<GroundQuad> = chunks
+            .iter()
+            .flat_map(|(min, max)| greedy_top_quads(&map, *min, *max))
+            .collect();
+        assert_covers(&map, &quads);
+        assert!(quads.iter().all(|q| q.size.x <= GROUND_CHUNK_SIZE && q.size.y <= GROUND_CHUNK_SIZE));
+    }
+}
@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+
+/// Segments shorter than this are skipped, the target is already standing on their end
+const MIN_SEGMENT_LENGTH: f32 = 1e-4;
+
+/// Earliest time `t >= 0` a projectile fired from the origin at `projectile_speed` reaches a target that is at
+/// `offset` after `delay` seconds and keeps moving at `velocity`. `t` is counted from the end of the delay,
+/// the projectile has been flying for the whole of it.
+fn time_to_reach(offset: Vec3, velocity: Vec3, projectile_speed: f32, delay: f32) -> Option<f32> {
+    // |offset + velocity * t| = projectile_speed * (delay + t)
+    let a = velocity.length_squared() - projectile_speed * projectile_speed;
+    let b = 2.0 * (offset.dot(velocity) - projectile_speed * projectile_speed * delay);
+    let c = offset.length_squared() - (projectile_speed * delay).powi(2);
+    if c <= 0.0 {
+        // The projectile got here before the target did
+        return Some(0.0);
+    }
+    if a.abs() < f32::EPSILON {
+        // As fast as the projectile, it only catches up when the target comes towards it
+        return match b < 0.0 {
+            true => Some(-c / b),
+            false => None,
+        };
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    [(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+        .into_iter()
+        .filter(|t| *t >= 0.0)
+        .min_by(f32::total_cmp)
+}
+
+/// Where a projectile fired now from `shooter` meets a target that walks from `position` through every point
+/// of `route` at `speed`, and carries on in a straight line past the last one.
+/// Returns the meeting point and the flight time, or None when the projectile never catches up.
+pub fn intercept_on_route(
+    shooter: Vec3,
+    projectile_speed: f32,
+    position: Vec3,
+    speed: f32,
+    route: &[Vec3],
+) -> Option<(Vec3, f32)> {
+    if projectile_speed <= 0.0 {
+        return None;
+    }
+    let standing_still = Some((position, position.distance(shooter) / projectile_speed));
+    if speed <= 0.0 {
+        return standing_still;
+    }
+
+    let mut start = position;
+    let mut elapsed = 0.0;
+    let mut heading = None;
+    for &point in route {
+        let segment = point - start;
+        let length = segment.length();
+        if length < MIN_SEGMENT_LENGTH {
+            continue;
+        }
+        let direction = segment / length;
+        let duration = length / speed;
+        if let Some(t) = time_to_reach(start - shooter, direction * speed, projectile_speed, elapsed) {
+            if t <= duration {
+                return Some((start + direction * speed * t, elapsed + t));
+            }
+        }
+        start = point;
+        elapsed += duration;
+        heading = Some(direction);
+    }
+
+    match heading {
+        Some(direction) => time_to_reach(start - shooter, direction * speed, projectile_speed, elapsed)
+            .map(|t| (start + direction * speed * t, elapsed + t)),
+        // Nowhere to go
+        None => standing_still,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.distance(b) < 1e-3, "{} is not {}", a, b);
+    }
+
+    /// The projectile covers the distance to the meeting point in exactly the flight time
+    fn assert_meets(shooter: Vec3, projectile_speed: f32, (point, time): (Vec3, f32)) {
+        assert!((shooter.distance(point) - projectile_speed * time).abs() < 1e-3);
+    }
+
+    #[test]
+    fn standing_target_is_hit_where_it_stands() {
+        let hit = intercept_on_route(Vec3::ZERO, 2.0, Vec3::new(4.0, 0.0, 0.0), 0.0, &[Vec3::new(4.0, 0.0, 9.0)]).unwrap();
+        assert_near(hit.0, Vec3::new(4.0, 0.0, 0.0));
+        assert!((hit.1 - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn leads_a_target_crossing_in_front() {
+        // Walking across at 3, the projectile flies at 5: a 3-4-5 triangle reached after one second
+        let hit = intercept_on_route(Vec3::ZERO, 5.0, Vec3::new(0.0, 0.0, 4.0), 3.0, &[Vec3::new(10.0, 0.0, 4.0)]).unwrap();
+        assert_near(hit.0, Vec3::new(3.0, 0.0, 4.0));
+        assert!((hit.1 - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn meets_a_target_walking_towards_the_shooter() {
+        let hit = intercept_on_route(Vec3::ZERO, 3.0, Vec3::new(8.0, 0.0, 0.0), 1.0, &[Vec3::ZERO]).unwrap();
+        assert_near(hit.0, Vec3::new(6.0, 0.0, 0.0));
+        assert_meets(Vec3::ZERO, 3.0, hit);
+    }
+
+    #[test]
+    fn follows_the_turn_at_the_next_waypoint() {
+        // One unit to the corner, then straight up past the shooter's side
+        let route = [Vec3::new(5.0, 0.0, 0.0), Vec3::new(5.0, 0.0, 10.0)];
+        let hit = intercept_on_route(Vec3::new(0.0, 0.0, 3.0), 2.0, Vec3::new(4.0, 0.0, 0.0), 2.0, &route).unwrap();
+        assert!((hit.0.x - 5.0).abs() < 1e-4, "should meet after the turn, not at {}", hit.0);
+        assert!(hit.0.z > 0.0);
+        assert_meets(Vec3::new(0.0, 0.0, 3.0), 2.0, hit);
+    }
+
+    #[test]
+    fn keeps_walking_past_the_end_of_the_route() {
+        let hit = intercept_on_route(Vec3::ZERO, 5.0, Vec3::new(0.0, 0.0, 4.0), 3.0, &[Vec3::new(1.0, 0.0, 4.0)]).unwrap();
+        assert_near(hit.0, Vec3::new(3.0, 0.0, 4.0));
+    }
+
+    #[test]
+    fn cannot_catch_a_faster_target_running_away() {
+        let hit = intercept_on_route(Vec3::ZERO, 2.0, Vec3::new(3.0, 0.0, 0.0), 4.0, &[Vec3::new(20.0, 0.0, 0.0)]);
+        assert_eq!(hit, None);
+    }
+
+    #[test]
+    fn catches_a_faster_target_once_it_turns_back() {
+        let route = [Vec3::new(5.0, 0.0, 0.0), Vec3::new(-20.0, 0.0, 0.0)];
+        let hit = intercept_on_route(Vec3::ZERO, 2.0, Vec3::new(3.0, 0.0, 0.0), 4.0, &route).unwrap();
+        assert_meets(Vec3::ZERO, 2.0, hit);
+        assert!(hit.1 > 0.5, "the target has to reach the turn first");
+    }
+
+    #[test]
+    fn height_difference_counts_towards_the_flight() {
+        let hit = intercept_on_route(Vec3::new(0.0, 3.0, 0.0), 5.0, Vec3::new(4.0, 0.0, 0.0), 0.0, &[]).unwrap();
+        assert!((hit.1 - 1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,31 @@
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::reflect::TypeUuid;
+use crate::helpers::Root;
+
+/// A parsed LDtk project, the [Campaign](crate::gameplay::Campaign) builds the maps of its levels
+#[derive(TypeUuid, Debug, Clone)]
+#[uuid = "6f1c7a0e-2b1d-4c8a-9a57-3f3f0d2c8e41"]
+pub struct LdtkProject {
+    pub root: Root,
+}
+
+#[derive(Default)]
+pub struct LdtkLoader;
+
+impl AssetLoader for LdtkLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let root = Root::from_json(std::str::from_utf8(bytes)?)?;
+            load_context.set_default_asset(LoadedAsset::new(LdtkProject { root }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ldtk"]
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use serde_derive::Serialize;
+use crate::gameplay::GameMap;
+use crate::helpers::{CellGrid, CellType, Defs, Fields, ENTITY_LAYER, FINISH_ENTITY, INT_GRID_LAYER, Level, MAZING_FIELD, SPAWN_ENTITY, STARTING_FUNDS_FIELD, STARTING_LIVES_FIELD, WAYPOINT_ENTITY};
+use crate::target::MovementMode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The level loads, but probably not the way it was meant to
+    Warning,
+    /// The level can not be played
+    Error,
+}
+
+/// A single problem found in a level file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LevelIssue {
+    /// None when the problem is with the file itself
+    pub level: Option<String>,
+    pub severity: Severity,
+    pub message: String,
+    /// Grid cell the problem is at
+    pub cell: Option<(i64, i64)>,
+}
+
+impl LevelIssue {
+    pub fn error(level: Option<&str>, message: String, cell: Option<(i64, i64)>) -> Self {
+        LevelIssue { level: level.map(str::to_string), severity: Severity::Error, message, cell }
+    }
+
+    pub fn warning(level: Option<&str>, message: String, cell: Option<(i64, i64)>) -> Self {
+        LevelIssue { level: level.map(str::to_string), severity: Severity::Warning, message, cell }
+    }
+}
+
+/// Collects every problem with a level instead of stopping at the first one like the game does
+pub fn validate_level(level: &Level, defs: &Defs) -> Vec<LevelIssue> {
+    let name = Some(level.identifier.as_str());
+    let mut issues = Vec::new();
+
+    for field in [STARTING_LIVES_FIELD, STARTING_FUNDS_FIELD] {
+        if !level.has_field(field) {
+            issues.push(LevelIssue::error(name, format!("missing level field {}", field), None));
+        } else if let Err(e) = level.get_u32(field) {
+            issues.push(LevelIssue::error(name, e.to_string(), None));
+        }
+    }
+    let mazing = match level.get_u32(MAZING_FIELD) {
+        Ok(mazing) => mazing.unwrap_or(0) != 0,
+        Err(e) => {
+            issues.push(LevelIssue::error(name, e.to_string(), None));
+            false
+        }
+    };
+
+    let (entity_layer, int_grid_layer) = match (level.layer(ENTITY_LAYER), level.layer(INT_GRID_LAYER)) {
+        (Ok(entities), Ok(int_grid)) => (entities, int_grid),
+        (entities, int_grid) => {
+            for e in [entities.err(), int_grid.err()].into_iter().flatten() {
+                issues.push(LevelIssue::error(name, e.to_string(), None));
+            }
+            return issues;
+        }
+    };
+    let entities = &entity_layer.entity_instances;
+    let cells = CellGrid::from_layer(int_grid_layer, defs.int_grid_values(int_grid_layer.layer_def_uid));
+
+    for (identifier, count) in [SPAWN_ENTITY, FINISH_ENTITY].map(|id| (id, entities.iter().filter(|e| e.identifier == id).count())) {
+        if count == 0 {
+            issues.push(LevelIssue::error(name, format!("level has no {} entity", identifier), None));
+        }
+    }
+
+    let mut occupied: BTreeMap<(i64, i64), Vec<&str>> = BTreeMap::new();
+    for entity in entities {
+        let (x, y) = match entity.grid_position() {
+            Ok(position) => position,
+            Err(e) => {
+                issues.push(LevelIssue::error(name, e.to_string(), None));
+                continue;
+            }
+        };
+        occupied.entry((x, y)).or_default().push(&entity.identifier);
+
+        let cell = match (usize::try_from(x), usize::try_from(y)) {
+            (Ok(cx), Ok(cy)) => cells.get(cx, cy),
+            _ => None,
+        };
+        match cell {
+            None => issues.push(LevelIssue::error(
+                name,
+                format!("{} is outside the {}x{} grid", entity.identifier, cells.width, cells.height),
+                Some((x, y)),
+            )),
+            // Mazing enemies pathfind and never look at the waypoints
+            Some(c) if entity.identifier == WAYPOINT_ENTITY && c != CellType::Path => {
+                let message = format!("{} is not on a path cell", entity.identifier);
+                issues.push(match mazing {
+                    true => LevelIssue::warning(name, message, Some((x, y))),
+                    false => LevelIssue::error(name, message, Some((x, y))),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for (cell, identifiers) in occupied.into_iter().filter(|(_, ids)| ids.len() > 1) {
+        issues.push(LevelIssue::error(name, format!("overlapping entities: {}", identifiers.join(", ")), Some(cell)));
+    }
+
+    if issues.iter().any(|i| i.severity == Severity::Error) {
+        // The map can not be built anyway, the reasons have already been reported
+        return issues;
+    }
+    let map = match GameMap::from_level(level, defs) {
+        Ok(map) => map,
+        Err(e) => {
+            issues.push(LevelIssue::error(name, e.to_string(), None));
+            return issues;
+        }
+    };
+
+    for &spawn in &map.paths.spawns {
+        let cell = map.world_to_cell(map.paths.nodes[spawn].position);
+        let grid_cell = cell.map(|c| (c.x as i64, c.y as i64));
+        let distance = match map.movement {
+            MovementMode::Waypoints => Some(map.paths.nodes[spawn].remaining).filter(|d| d.is_finite()),
+            MovementMode::Pathfinding => cell.and_then(|c| map.flow_field.distance(c)).map(|d| d as f32),
+        };
+        match distance {
+            None => issues.push(LevelIssue::error(name, "no Finish can be reached from this Spawn".to_string(), grid_cell)),
+            Some(d) if d <= 0.0 => issues.push(LevelIssue::error(name, "zero length path, the Spawn is on a Finish".to_string(), grid_cell)),
+            Some(_) => {}
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use super::*;
+    use crate::helpers::Root;
+
+    /// Validates the 4x3 fixture level after `edit` changed its json: a path row in the middle, from a
+    /// Spawn through a Waypoint to a Finish, buildable cells above and raised cells at the bottom left
+    fn validate_fixture(edit: impl FnOnce(&mut Value)) -> Vec<LevelIssue> {
+        let path = format!("{}/tests/fixtures/ldtk/ldtk_1_5_3.ldtk", env!("CARGO_MANIFEST_DIR"));
+        let mut project: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        edit(&mut project["levels"][0]);
+        let root = Root::from_json(&project.to_string()).unwrap();
+        validate_level(&root.levels[0], &root.defs)
+    }
+
+    fn entities(level: &mut Value) -> &mut Vec<Value> {
+        level["layerInstances"][0]["entityInstances"].as_array_mut().unwrap()
+    }
+
+    fn add_field(level: &mut Value, identifier: &str, field_type: &str, value: Value) {
+        level["fieldInstances"].as_array_mut().unwrap().push(json!({
+            "__identifier": identifier,
+            "__type": field_type,
+            "__value": value,
+        }));
+    }
+
+    fn errors(issues: &[LevelIssue]) -> Vec<&str> {
+        issues
+            .iter()
+            .filter(|i| i.severity == Severity::Error)
+            .map(|i| i.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn the_fixture_level_is_fine() {
+        assert_eq!(validate_fixture(|_| {}), Vec::new());
+    }
+
+    #[test]
+    fn reports_a_missing_spawn() {
+        let issues = validate_fixture(|level| entities(level).retain(|e| e["__identifier"] != "Spawn"));
+        assert_eq!(errors(&issues), vec!["level has no Spawn entity"]);
+    }
+
+    #[test]
+    fn reports_a_missing_finish() {
+        let issues = validate_fixture(|level| entities(level).retain(|e| e["__identifier"] != "Finish"));
+        assert_eq!(errors(&issues), vec!["level has no Finish entity"]);
+    }
+
+    #[test]
+    fn reports_a_waypoint_pointing_at_a_missing_entity() {
+        let issues = validate_fixture(|level| {
+            let waypoint = entities(level).iter_mut().find(|e| e["__identifier"] == "Waypoint").unwrap();
+            waypoint["fieldInstances"][3]["__value"][0]["entityIid"] = json!("gone-1");
+        });
+        let errors = errors(&issues);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("points at missing entity gone-1"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn reports_a_finish_the_spawn_can_not_reach() {
+        let issues = validate_fixture(|level| {
+            add_field(level, "mazing", "Int", json!(1));
+            // Wall off the right half with raised cells
+            level["layerInstances"][1]["intGridCsv"] = json!([2, 2, 3, 2, 1, 1, 3, 1, 3, 3, 3, 2]);
+        });
+        assert_eq!(errors(&issues), vec!["no Finish can be reached from this Spawn"]);
+        assert_eq!(issues.iter().find(|i| i.severity == Severity::Error).unwrap().cell, Some((0, 1)));
+        // The Waypoint is ignored when mazing, so sitting on the wall is only a warning
+        assert!(issues.iter().any(|i| i.severity == Severity::Warning && i.cell == Some((2, 1))));
+    }
+
+    #[test]
+    fn reports_bad_wave_json() {
+        let issues = validate_fixture(|level| add_field(level, "waves", "String", json!("[{\"groups\": ")));
+        let errors = errors(&issues);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("malformed field Level_0.waves"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let issues = validate_fixture(|level| {
+            level["fieldInstances"].as_array_mut().unwrap().retain(|f| f["__identifier"] != "starting_lives");
+            entities(level).retain(|e| e["__identifier"] != "Spawn");
+        });
+        assert_eq!(errors(&issues), vec!["missing level field starting_lives", "level has no Spawn entity"]);
+    }
+}
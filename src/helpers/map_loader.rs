@@ -1,145 +1,58 @@
+use std::fmt;
 use std::fs;
 use bevy::math::Vec2;
 use bevy::prelude::*;
 
+use serde::Deserializer;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 use serde_json::Value;
 use crate::gameplay::GameMap;
+use crate::helpers::{CellGrid, Fields, FlowField, ForkChoice, PathGraph, PathNode, WaveSet};
+use crate::target::MovementMode;
+
+// Only the parts of the LDtk schema the game reads are declared here. Every struct falls back to
+// its defaults for missing fields and unknown fields are skipped, so files written by newer (or
+// older) editors keep loading as long as these fields keep their meaning.
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct Root {
-    #[serde(rename = "__header__")]
-    pub header: Header,
-    pub iid: String,
     pub json_version: String,
-    pub app_build_id: i64,
-    pub next_uid: i64,
-    pub identifier_style: String,
-    pub world_layout: String,
-    pub world_grid_width: i64,
-    pub world_grid_height: i64,
-    pub default_level_width: i64,
-    pub default_level_height: i64,
-    pub default_pivot_x: i64,
-    pub default_pivot_y: i64,
-    pub default_grid_size: i64,
-    pub bg_color: String,
-    pub default_level_bg_color: String,
-    pub minify_json: bool,
-    pub external_levels: bool,
-    pub export_tiled: bool,
-    pub simplified_export: bool,
-    pub image_export_mode: String,
-    pub export_level_bg: bool,
-    pub png_file_pattern: Value,
-    pub backup_on_save: bool,
-    pub backup_limit: i64,
-    pub level_name_pattern: String,
-    pub tutorial_desc: Value,
-    pub custom_commands: Vec<Value>,
-    pub flags: Vec<Value>,
     pub defs: Defs,
     pub levels: Vec<Level>,
-    pub worlds: Vec<Value>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Header {
-    pub file_type: String,
-    pub app: String,
-    pub doc: String,
-    pub schema: String,
-    pub app_author: String,
-    pub app_version: String,
-    pub url: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct Defs {
     pub layers: Vec<Layer>,
-    pub entities: Vec<Entity>,
-    pub tilesets: Vec<Tileset>,
-    pub enums: Vec<Enum>,
-    pub external_enums: Vec<Value>,
-    pub level_fields: Vec<LevelField>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct Layer {
     #[serde(rename = "__type")]
     pub type_field: String,
     pub identifier: String,
-    #[serde(rename = "type")]
-    pub type_field2: String,
     pub uid: i64,
     pub grid_size: i64,
-    pub guide_grid_wid: i64,
-    pub guide_grid_hei: i64,
-    pub display_opacity: i64,
-    pub inactive_opacity: f64,
-    pub hide_in_list: bool,
-    pub hide_fields_when_inactive: bool,
-    pub can_select_when_inactive: bool,
-    pub px_offset_x: i64,
-    pub px_offset_y: i64,
-    pub parallax_factor_x: i64,
-    pub parallax_factor_y: i64,
-    pub parallax_scaling: bool,
-    pub required_tags: Vec<Value>,
-    pub excluded_tags: Vec<Value>,
     pub int_grid_values: Vec<IntGridValue>,
-    pub auto_rule_groups: Vec<Value>,
-    pub auto_source_layer_def_uid: Value,
-    pub tileset_def_uid: Value,
-    pub tile_pivot_x: i64,
-    pub tile_pivot_y: i64,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct IntGridValue {
     pub value: i64,
+    /// Optional in the editor
+    #[serde(deserialize_with = "null_as_default")]
     pub identifier: String,
+    #[serde(deserialize_with = "null_as_default")]
     pub color: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Entity {
-    pub identifier: String,
-    pub uid: i64,
-    pub tags: Vec<Value>,
-    pub width: i64,
-    pub height: i64,
-    pub resizable_x: bool,
-    pub resizable_y: bool,
-    pub keep_aspect_ratio: bool,
-    pub tile_opacity: i64,
-    pub fill_opacity: f64,
-    pub line_opacity: i64,
-    pub hollow: bool,
-    pub color: String,
-    pub render_mode: String,
-    pub show_name: bool,
-    pub tileset_id: i64,
-    pub tile_render_mode: String,
-    pub tile_rect: TileRect,
-    pub nine_slice_borders: Vec<Value>,
-    pub max_count: i64,
-    pub limit_scope: String,
-    pub limit_behavior: String,
-    pub pivot_x: i64,
-    pub pivot_y: i64,
-    pub field_defs: Vec<Value>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct TileRect {
     pub tileset_uid: i64,
     pub x: i64,
@@ -149,140 +62,30 @@ pub struct TileRect {
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Tileset {
-    #[serde(rename = "__cWid")]
-    pub c_wid: i64,
-    #[serde(rename = "__cHei")]
-    pub c_hei: i64,
-    pub identifier: String,
-    pub uid: i64,
-    pub rel_path: Value,
-    pub embed_atlas: String,
-    pub px_wid: i64,
-    pub px_hei: i64,
-    pub tile_grid_size: i64,
-    pub spacing: i64,
-    pub padding: i64,
-    pub tags: Vec<Value>,
-    pub tags_source_enum_uid: Value,
-    pub enum_tags: Vec<Value>,
-    pub custom_data: Vec<Value>,
-    pub saved_selections: Vec<Value>,
-    pub cached_pixel_data: CachedPixelData,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct CachedPixelData {
-    pub opaque_tiles: String,
-    pub average_colors: String,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Enum {
-    pub identifier: String,
-    pub uid: i64,
-    pub values: Vec<Value>,
-    pub icon_tileset_uid: i64,
-    pub external_rel_path: Value,
-    pub external_file_checksum: Value,
-    pub tags: Vec<Value>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct LevelField {
-    pub identifier: String,
-    pub doc: Value,
-    #[serde(rename = "__type")]
-    pub type_field: String,
-    pub uid: i64,
-    #[serde(rename = "type")]
-    pub type_field2: String,
-    pub is_array: bool,
-    pub can_be_null: bool,
-    pub array_min_length: Value,
-    pub array_max_length: Value,
-    pub editor_display_mode: String,
-    pub editor_display_pos: String,
-    pub editor_link_style: String,
-    pub editor_always_show: bool,
-    pub editor_show_in_world: bool,
-    pub editor_cut_long_values: bool,
-    pub editor_text_suffix: Value,
-    pub editor_text_prefix: Value,
-    pub use_for_smart_color: bool,
-    pub min: Value,
-    pub max: Value,
-    pub regex: Value,
-    pub accept_file_types: Value,
-    pub default_override: DefaultOverride,
-    pub text_language_mode: Value,
-    pub symmetrical_ref: bool,
-    pub auto_chain_ref: bool,
-    pub allow_out_of_level_ref: bool,
-    pub allowed_refs: String,
-    pub allowed_ref_tags: Vec<Value>,
-    pub tileset_uid: Value,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct DefaultOverride {
-    pub id: String,
-    pub params: Vec<i64>,
-}
-
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct Level {
     pub identifier: String,
     pub iid: String,
-    pub uid: i64,
-    pub world_x: i64,
-    pub world_y: i64,
-    pub world_depth: i64,
-    pub px_wid: i64,
-    pub px_hei: i64,
-    #[serde(rename = "__bgColor")]
-    pub bg_color: String,
-    #[serde(rename = "bgColor")]
-    pub bg_color2: Value,
-    pub use_auto_identifier: bool,
-    pub bg_rel_path: Value,
-    pub bg_pos: Value,
-    pub bg_pivot_x: f64,
-    pub bg_pivot_y: f64,
-    #[serde(rename = "__smartColor")]
-    pub smart_color: String,
-    #[serde(rename = "__bgPos")]
-    pub bg_pos2: Value,
-    pub external_rel_path: Value,
     pub field_instances: Vec<FieldInstance>,
+    /// Null when the project saves levels in separate files
+    #[serde(deserialize_with = "null_as_default")]
     pub layer_instances: Vec<LayerInstance>,
-    #[serde(rename = "__neighbours")]
-    pub neighbours: Vec<Value>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(default)]
 pub struct FieldInstance {
     #[serde(rename = "__identifier")]
     pub identifier: String,
+    /// Raw value, read it through the [Fields] accessors to get a typed [FieldValue]
     #[serde(rename = "__value")]
-    pub value: i64,
+    pub value: Value,
     #[serde(rename = "__type")]
     pub type_field: String,
-    #[serde(rename = "__tile")]
-    pub tile: Value,
-    pub def_uid: i64,
-    pub real_editor_values: Vec<Value>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct LayerInstance {
     #[serde(rename = "__identifier")]
     pub identifier: String,
@@ -294,99 +97,548 @@ pub struct LayerInstance {
     pub c_hei: i64,
     #[serde(rename = "__gridSize")]
     pub grid_size: i64,
-    #[serde(rename = "__opacity")]
-    pub opacity: i64,
-    #[serde(rename = "__pxTotalOffsetX")]
-    pub px_total_offset_x: i64,
-    #[serde(rename = "__pxTotalOffsetY")]
-    pub px_total_offset_y: i64,
-    #[serde(rename = "__tilesetDefUid")]
-    pub tileset_def_uid: Value,
-    #[serde(rename = "__tilesetRelPath")]
-    pub tileset_rel_path: Value,
-    pub iid: String,
-    pub level_id: i64,
     pub layer_def_uid: i64,
-    pub px_offset_x: i64,
-    pub px_offset_y: i64,
-    pub visible: bool,
-    pub optional_rules: Vec<Value>,
     pub int_grid_csv: Vec<i64>,
-    pub auto_layer_tiles: Vec<Value>,
-    pub seed: i64,
-    pub override_tileset_uid: Value,
-    pub grid_tiles: Vec<Value>,
     pub entity_instances: Vec<EntityInstance>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct EntityInstance {
     #[serde(rename = "__identifier")]
     pub identifier: String,
     #[serde(rename = "__grid")]
     pub grid: Vec<i64>,
-    #[serde(rename = "__pivot")]
-    pub pivot: Vec<i64>,
-    #[serde(rename = "__tags")]
-    pub tags: Vec<Value>,
-    #[serde(rename = "__tile")]
-    pub tile: Tile,
-    #[serde(rename = "__smartColor")]
-    pub smart_color: String,
     pub iid: String,
-    pub width: i64,
-    pub height: i64,
-    pub def_uid: i64,
-    pub px: Vec<i64>,
-    pub field_instances: Vec<Value>,
+    pub field_instances: Vec<FieldInstance>,
 }
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Tile {
-    pub tileset_uid: i64,
-    pub x: i64,
-    pub y: i64,
-    pub w: i64,
-    pub h: i64,
+/// LDtk writes null for a lot of optional values, read those as the default
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: Deserializer<'de>, T: Default + serde::Deserialize<'de>
+{
+    <Option<T> as serde::Deserialize>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+pub const ENTITY_LAYER: &str = "Entities";
+pub const SPAWN_ENTITY: &str = "Spawn";
+pub const WAYPOINT_ENTITY: &str = "Waypoint";
+pub const FINISH_ENTITY: &str = "Finish";
+/// Int level fields every level needs
+pub const STARTING_LIVES_FIELD: &str = "starting_lives";
+pub const STARTING_FUNDS_FIELD: &str = "starting_funds";
+/// Optional Multilines level field with the waves of the level as JSON, see [WaveSet]
+pub const WAVES_FIELD: &str = "waves";
+/// Optional Int level field, non zero makes the level a mazing level
+pub const MAZING_FIELD: &str = "mazing";
+/// Optional Int field on waypoints, lower goes first
+pub const ORDER_FIELD: &str = "order";
+/// Optional EntityRef (or array of EntityRef) field on Spawns and Waypoints pointing at the next stop(s)
+pub const NEXT_FIELD: &str = "next";
+/// Optional Float field, how likely a weighted fork sends enemies to this entity
+pub const WEIGHT_FIELD: &str = "weight";
+/// Optional String/Enum field on forks: Random, Weighted or Shortest
+pub const FORK_FIELD: &str = "fork";
+pub const INT_GRID_LAYER: &str = "IntGrid";
+/// Oldest LDtk json version the loader can read
+pub const MIN_JSON_VERSION: JsonVersion = JsonVersion(1, 0, 0);
+/// Newest LDtk json version the loader has been tested with, newer 1.x files load with a warning
+pub const MAX_TESTED_JSON_VERSION: JsonVersion = JsonVersion(1, 5, 3);
+
+/// major.minor.patch of the `jsonVersion` an LDtk file was saved with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct JsonVersion(pub u32, pub u32, pub u32);
+
+impl JsonVersion {
+    /// Reads versions like "1.2.3", missing parts count as 0 and suffixes like "-beta" are ignored
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.trim().split('.').map(|p| {
+            let digits: String = p.chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse::<u32>().ok()
+        });
+        let major = parts.next()??;
+        let minor = parts.next().unwrap_or(Some(0))?;
+        let patch = parts.next().unwrap_or(Some(0))?;
+        Some(JsonVersion(major, minor, patch))
+    }
+}
+
+impl fmt::Display for JsonVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
 }
 
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    LevelNotFound(LevelSelector),
+    MissingLayer(&'static str),
+    NoSpawn,
+    NoFinish,
+    UnsupportedJsonVersion(String),
+    MalformedField { field: String, reason: String },
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapLoadError::Io(e) => write!(f, "could not read level file: {}", e),
+            MapLoadError::Parse(e) => write!(f, "could not parse level file: {}", e),
+            MapLoadError::LevelNotFound(selector) => write!(f, "level {:?} does not exist", selector),
+            MapLoadError::MissingLayer(layer) => write!(f, "level has no {} layer", layer),
+            MapLoadError::NoSpawn => write!(f, "level has no Spawn entity"),
+            MapLoadError::NoFinish => write!(f, "level has no Finish entity"),
+            MapLoadError::UnsupportedJsonVersion(v) => write!(
+                f,
+                "unsupported LDtk json version {:?}, expected {} up to {}.x",
+                v, MIN_JSON_VERSION, MIN_JSON_VERSION.0,
+            ),
+            MapLoadError::MalformedField { field, reason } => write!(f, "malformed field {}: {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for MapLoadError {}
+
+impl From<std::io::Error> for MapLoadError {
+    fn from(e: std::io::Error) -> Self {
+        MapLoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MapLoadError {
+    fn from(e: serde_json::Error) -> Self {
+        MapLoadError::Parse(e)
+    }
+}
+
+/// Picks a single level out of an LDtk project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelSelector {
+    Index(usize),
+    Identifier(String),
+}
+
+impl Default for LevelSelector {
+    fn default() -> Self {
+        LevelSelector::Index(0)
+    }
+}
+
+impl Root {
+    pub fn from_file(path: &str) -> Result<Self, MapLoadError> {
+        info!("loading ldtk project: {}", path);
+        let data = fs::read_to_string(path)?;
+        Self::from_json(&data)
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, MapLoadError> {
+        // Check the version before the schema, files from an unsupported editor may not parse at all
+        let raw: Value = serde_json::from_str(data)?;
+        let version = raw.get("jsonVersion").and_then(|v| v.as_str()).unwrap_or_default();
+        match JsonVersion::parse(version) {
+            Some(v) if v >= MIN_JSON_VERSION && v.0 == MIN_JSON_VERSION.0 => {
+                if v > MAX_TESTED_JSON_VERSION {
+                    warn!("LDtk json version {} is newer than {}, the newest one tested, some data may be ignored", v, MAX_TESTED_JSON_VERSION);
+                }
+            }
+            _ => return Err(MapLoadError::UnsupportedJsonVersion(version.to_string())),
+        }
+
+        let root_data: Root = serde_json::from_value(raw)?;
+        Ok(root_data)
+    }
+
+    /// Identifiers of every level in the project, in the order they appear in the file
+    pub fn level_identifiers(&self) -> Vec<&str> {
+        self.levels.iter().map(|l| l.identifier.as_str()).collect()
+    }
+
+    pub fn level_index(&self, selector: &LevelSelector) -> Option<usize> {
+        match selector {
+            LevelSelector::Index(i) => (*i < self.levels.len()).then_some(*i),
+            LevelSelector::Identifier(id) => self.levels.iter().position(|l| &l.identifier == id),
+        }
+    }
+
+    pub fn level(&self, selector: &LevelSelector) -> Option<&Level> {
+        self.level_index(selector).map(|i| &self.levels[i])
+    }
+}
+
+impl Defs {
+    /// IntGrid values declared for a layer definition
+    pub fn int_grid_values(&self, layer_def_uid: i64) -> &[IntGridValue] {
+        self.layers
+            .iter()
+            .find(|l| l.uid == layer_def_uid)
+            .map(|l| l.int_grid_values.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+impl Level {
+    /// Finds a layer by its identifier, falling back to the first layer of the same type
+    /// so renaming a layer in the editor does not break the map
+    pub fn layer(&self, layer_type: &'static str) -> Result<&LayerInstance, MapLoadError> {
+        self.layer_instances
+            .iter()
+            .find(|l| l.identifier == layer_type)
+            .or_else(|| self.layer_instances.iter().find(|l| l.type_field == layer_type))
+            .ok_or(MapLoadError::MissingLayer(layer_type))
+    }
+}
+
+impl Fields for Level {
+    fn field_instances(&self) -> &[FieldInstance] {
+        &self.field_instances
+    }
+
+    fn owner(&self) -> &str {
+        &self.identifier
+    }
+}
+
+impl EntityInstance {
+    pub fn grid_position(&self) -> Result<(i64, i64), MapLoadError> {
+        match self.grid[..] {
+            [x, y] => Ok((x, y)),
+            _ => Err(MapLoadError::MalformedField {
+                field: format!("{}.__grid", self.identifier),
+                reason: format!("expected 2 coordinates, got {}", self.grid.len()),
+            }),
+        }
+    }
+
+    pub fn order(&self) -> Result<Option<i64>, MapLoadError> {
+        self.get_i64(ORDER_FIELD)
+    }
+
+    pub fn weight(&self) -> Result<f32, MapLoadError> {
+        Ok(self.get_f32(WEIGHT_FIELD)?.unwrap_or(1.0))
+    }
+
+    /// The fork field can be a String or an Enum field
+    pub fn fork_choice(&self) -> Result<ForkChoice, MapLoadError> {
+        match self.get(FORK_FIELD)? {
+            None => Ok(ForkChoice::default()),
+            Some(v) => v.as_str().and_then(ForkChoice::from_name).ok_or_else(|| {
+                self.malformed(FORK_FIELD, format!("{:?} is not one of Random, Weighted or Shortest", v))
+            }),
+        }
+    }
+}
+
+impl Fields for EntityInstance {
+    fn field_instances(&self) -> &[FieldInstance] {
+        &self.field_instances
+    }
+
+    fn owner(&self) -> &str {
+        &self.identifier
+    }
+}
+
+/// Builds the path graph out of the Spawn, Waypoint and Finish entities.
+///
+/// Spawns and Waypoints with a `next` reference (or array of references, making a fork) lead to the
+/// entities they point at, lanes merge by pointing at the same entity.
+/// A Spawn without references visits every Waypoint that is not part of a referenced lane, sorted by
+/// their `order` field (falling back to the order they were placed in), and ends at the Finish closest to the last stop.
+/// Every lane has to end on a Finish.
+pub fn path_graph(entities: &[EntityInstance], to_world: impl Fn(i64, i64) -> Vec2) -> Result<PathGraph, MapLoadError> {
+    let path_entities: Vec<&EntityInstance> = entities
+        .iter()
+        .filter(|e| [SPAWN_ENTITY, WAYPOINT_ENTITY, FINISH_ENTITY].contains(&e.identifier.as_str()))
+        .collect();
+
+    let mut graph = PathGraph::default();
+    for entity in &path_entities {
+        let (x, y) = entity.grid_position()?;
+        let mut node = match entity.identifier == FINISH_ENTITY {
+            true => PathNode::finish(to_world(x, y)),
+            false => PathNode::new(to_world(x, y)),
+        };
+        node.weight = entity.weight()?;
+        node.fork_choice = entity.fork_choice()?;
+        graph.nodes.push(node);
+    }
+    let node_of = |entity: &EntityInstance| path_entities.iter().position(|e| e.iid == entity.iid).unwrap();
+
+    for (from, entity) in path_entities.iter().enumerate() {
+        if entity.identifier == FINISH_ENTITY {
+            continue;
+        }
+        for next in entity.get_entity_refs(NEXT_FIELD)? {
+            match path_entities.iter().position(|e| e.iid == next.entity_iid) {
+                None => return Err(MapLoadError::MalformedField {
+                    field: format!("{}.{}", entity.identifier, NEXT_FIELD),
+                    reason: format!("{} points at missing entity {}", entity.iid, next.entity_iid),
+                }),
+                Some(to) => graph.connect(from, to),
+            }
+        }
+    }
+
+    // Waypoints of referenced lanes are left out, chaining through them would fork or merge those lanes
+    let referenced = |node: usize| !graph.nodes[node].next.is_empty() || graph.nodes.iter().any(|n| n.next.contains(&node));
+    let mut ordered_waypoints = Vec::new();
+    for waypoint in path_entities.iter().filter(|e| e.identifier == WAYPOINT_ENTITY && !referenced(node_of(e))) {
+        ordered_waypoints.push((waypoint.order()?, *waypoint));
+    }
+    // Stable sort, so waypoints without an order stay in file order at the back
+    ordered_waypoints.sort_by_key(|(order, _)| order.unwrap_or(i64::MAX));
+
+    for spawn in path_entities.iter().filter(|e| e.identifier == SPAWN_ENTITY) {
+        graph.spawns.push(node_of(spawn));
+        if !graph.nodes[node_of(spawn)].next.is_empty() {
+            continue;
+        }
+
+        let mut route = vec![*spawn];
+        route.extend(ordered_waypoints.iter().map(|(_, w)| *w));
+        let last = route[route.len() - 1].grid_position()?;
+        let mut closest: Option<(i64, &EntityInstance)> = None;
+        for finish in path_entities.iter().filter(|e| e.identifier == FINISH_ENTITY) {
+            let (x, y) = finish.grid_position()?;
+            let distance = (x - last.0).pow(2) + (y - last.1).pow(2);
+            if !matches!(closest, Some((d, _)) if d <= distance) {
+                closest = Some((distance, finish));
+            }
+        }
+        match closest {
+            None => return Err(MapLoadError::NoFinish),
+            Some((_, finish)) => route.push(finish),
+        }
+
+        for stop in route.windows(2) {
+            graph.connect(node_of(stop[0]), node_of(stop[1]));
+        }
+    }
+
+    if graph.spawns.is_empty() {
+        return Err(MapLoadError::NoSpawn);
+    }
+
+    graph.compute_remaining();
+    if let Some(&dead_end) = graph.dead_ends().first() {
+        return Err(MapLoadError::MalformedField {
+            field: format!("{}.{}", path_entities[dead_end].identifier, NEXT_FIELD),
+            reason: format!("{} never leads to a Finish", path_entities[dead_end].iid),
+        });
+    }
+    Ok(graph)
+}
 
 impl GameMap {
-    pub fn load(level: &str) -> anyhow::Result<Self> {
-        info!("loading map: {}", level);
-        let data = fs::read_to_string(level)?;
-        let root_data: Root = serde_json::from_str(&data)?;
-        info!("Loaded map {}", root_data.levels[0].identifier);
-
-        let grid_cell_size = root_data.levels[0].layer_instances[1].grid_size as f32 / 4.0;
-        let map_width = root_data.levels[0].layer_instances[1].c_wid as f32;
-        let map_height = root_data.levels[0].layer_instances[1].c_hei as f32;
-
-        let mut waypoints: Vec<Vec2> = Vec::new();
-        for entity_wp in &root_data.levels[0].layer_instances[0].entity_instances {
-            waypoints.push(Vec2::new((entity_wp.grid[0] as f32 * grid_cell_size) + grid_cell_size / 2.0, (entity_wp.grid[1] as f32 * grid_cell_size) + grid_cell_size / 2.0));
+    pub fn load(path: &str, selector: &LevelSelector) -> Result<Self, MapLoadError> {
+        info!("loading map: {} ({:?})", path, selector);
+        let root_data = Root::from_file(path)?;
+        let level = match root_data.level(selector) {
+            None => return Err(MapLoadError::LevelNotFound(selector.clone())),
+            Some(l) => l,
+        };
+        Self::from_level(level, &root_data.defs)
+    }
+
+    pub fn from_level(level: &Level, defs: &Defs) -> Result<Self, MapLoadError> {
+        info!("Loaded map {}", level.identifier);
+        let entity_layer = level.layer(ENTITY_LAYER)?;
+        let int_grid_layer = level.layer(INT_GRID_LAYER)?;
+
+        let grid_cell_size = int_grid_layer.grid_size as f32 / 4.0;
+        let map_width = int_grid_layer.c_wid as f32;
+        let map_height = int_grid_layer.c_hei as f32;
+
+        if !entity_layer.entity_instances.iter().any(|e| e.identifier == SPAWN_ENTITY) {
+            return Err(MapLoadError::NoSpawn);
         }
+        if !entity_layer.entity_instances.iter().any(|e| e.identifier == FINISH_ENTITY) {
+            return Err(MapLoadError::NoFinish);
+        }
+
+        let paths = path_graph(&entity_layer.entity_instances, |x, y| {
+            Vec2::new((x as f32 * grid_cell_size) + grid_cell_size / 2.0, (y as f32 * grid_cell_size) + grid_cell_size / 2.0)
+        })?;
+
+        let cells = CellGrid::from_layer(int_grid_layer, defs.int_grid_values(int_grid_layer.layer_def_uid));
 
-        let starting_lives = match root_data.levels[0].field_instances.iter().find(|&v| v.identifier == "starting_lives") {
-            None => 0.,
-            Some(v) => v.value as f32
+        // Without any waypoints to follow enemies find their own way over the path cells.
+        // Mazing levels always pathfind, around the towers the player builds.
+        let mazing = level.get_u32(MAZING_FIELD)?.unwrap_or(0) != 0;
+        let movement = match mazing || !entity_layer.entity_instances.iter().any(|e| e.identifier == WAYPOINT_ENTITY) {
+            true => MovementMode::Pathfinding,
+            false => MovementMode::Waypoints,
         };
 
-        let starting_funds = match root_data.levels[0].field_instances.iter().find(|&v| v.identifier == "starting_funds") {
-            None => 0.,
-            Some(v) => v.value as f32
+        let waves = match level.get_string(WAVES_FIELD)? {
+            None => WaveSet::fallback(),
+            Some(json) => WaveSet::from_json(&json).map_err(|e| level.malformed(WAVES_FIELD, e.to_string()))?,
         };
+        if waves.waves.is_empty() {
+            return Err(level.malformed(WAVES_FIELD, "there has to be at least one wave".to_string()));
+        }
+        let spawn_count = paths.spawns.len();
+        if let Some(group) = waves.waves.iter().flat_map(|w| &w.groups).find(|g| g.spawn.is_some_and(|s| s >= spawn_count)) {
+            return Err(level.malformed(WAVES_FIELD, format!("{} uses spawn {:?}, the level only has {}", group.enemy, group.spawn, spawn_count)));
+        }
 
-        Ok(GameMap {
-            starting_lives: starting_lives as u32,
-            starting_funds: starting_funds as u32,
-            name: root_data.levels[0].identifier.to_string(),
+        let mut map = GameMap {
+            starting_lives: level.get_u32(STARTING_LIVES_FIELD)?.unwrap_or(0),
+            starting_funds: level.get_u32(STARTING_FUNDS_FIELD)?.unwrap_or(0),
+            name: level.identifier.to_string(),
             width: map_width,
             height: map_height,
-            waypoints,
+            paths,
             grid_size: grid_cell_size as u32,
-        })
+            cells,
+            movement,
+            flow_field: FlowField::default(),
+            mazing,
+            blocked: Default::default(),
+            waves,
+        };
+        map.rebuild_flow_field();
+        Ok(map)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::CellType;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/ldtk/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// The same 4x3 level saved by different editor versions
+    const FIXTURES: [&str; 3] = ["ldtk_1_0_0.ldtk", "ldtk_1_3_4.ldtk", "ldtk_1_5_3.ldtk"];
+
+    #[test]
+    fn loads_every_supported_version() {
+        for name in FIXTURES {
+            let root = Root::from_file(&fixture(name)).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let map = GameMap::from_level(&root.levels[0], &root.defs).unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+            assert_eq!(map.name, "Level_0", "{}", name);
+            assert_eq!((map.starting_lives, map.starting_funds), (10, 5), "{}", name);
+            assert_eq!((map.cells.width, map.cells.height), (4, 3), "{}", name);
+            assert_eq!(map.cells.cells_of_type(CellType::Path).count(), 4, "{}", name);
+            assert_eq!(map.cells.cells_of_type(CellType::Raised).count(), 2, "{}", name);
+            assert_eq!(map.tower_slots().len(), 6, "{}", name);
+            assert_eq!(map.paths.nodes.len(), 3, "{}", name);
+            assert_eq!(map.paths.spawns.len(), 1, "{}", name);
+            assert!(map.paths.nodes[map.paths.spawns[0]].remaining.is_finite(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn loads_the_game_levels() {
+        let root = Root::from_file(&format!("{}/assets/levels/test.ldtk", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        for level in &root.levels {
+            GameMap::from_level(level, &root.defs).unwrap();
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reads_fields_added_by_newer_versions() {
+        for name in &FIXTURES[1..] {
+            let root = Root::from_file(&fixture(name)).unwrap();
+            let level = &root.levels[0];
+            assert_eq!(level.get_string("intro").unwrap().as_deref(), Some("Hold the line!"), "{}", name);
+
+            let waypoint = level.layer(ENTITY_LAYER).unwrap().entity_instances
+                .iter()
+                .find(|e| e.identifier == WAYPOINT_ENTITY)
+                .unwrap();
+            assert_eq!(waypoint.order().unwrap(), Some(1), "{}", name);
+            assert_eq!(waypoint.weight().unwrap(), 0.5, "{}", name);
+            assert_eq!(waypoint.fork_choice().unwrap(), ForkChoice::ShortestRemaining, "{}", name);
+            assert_eq!(waypoint.get_entity_refs(NEXT_FIELD).unwrap()[0].entity_iid, "finish-1", "{}", name);
+
+            // IntGrid values without an identifier are allowed since 1.3
+            let int_grid = level.layer(INT_GRID_LAYER).unwrap();
+            assert!(root.defs.int_grid_values(int_grid.layer_def_uid).iter().any(|v| v.value == 4 && v.identifier.is_empty()), "{}", name);
+        }
+    }
+
+    /// A path entity at (x, 0) pointing at the entities with the `next` iids
+    fn path_entity(identifier: &str, iid: &str, x: i64, next: &[&str]) -> EntityInstance {
+        let refs: Vec<serde_json::Value> = next.iter().map(|iid| serde_json::json!({ "entityIid": iid, "layerIid": "", "levelIid": "", "worldIid": "" })).collect();
+        serde_json::from_value(serde_json::json!({
+            "__identifier": identifier,
+            "__grid": [x, 0],
+            "iid": iid,
+            "fieldInstances": [{ "__identifier": NEXT_FIELD, "__value": refs, "__type": "Array<EntityRef>" }],
+        })).unwrap()
+    }
+
+    fn to_world(x: i64, y: i64) -> Vec2 {
+        Vec2::new(x as f32, y as f32)
+    }
+
+    #[test]
+    fn rejects_lanes_ending_on_a_waypoint() {
+        let entities = [
+            path_entity(SPAWN_ENTITY, "spawn", 0, &["fork"]),
+            path_entity(WAYPOINT_ENTITY, "fork", 1, &["stop", "finish"]),
+            path_entity(WAYPOINT_ENTITY, "stop", 2, &[]),
+            path_entity(FINISH_ENTITY, "finish", 3, &[]),
+        ];
+        match path_graph(&entities, to_world) {
+            Err(MapLoadError::MalformedField { reason, .. }) => assert!(reason.contains("stop"), "{}", reason),
+            other => panic!("expected a dead end error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unreferenced_spawns_skip_waypoints_of_referenced_lanes() {
+        let entities = [
+            path_entity(SPAWN_ENTITY, "linked", 0, &["linked-wp"]),
+            path_entity(WAYPOINT_ENTITY, "linked-wp", 1, &["finish"]),
+            path_entity(SPAWN_ENTITY, "chained", 0, &[]),
+            path_entity(WAYPOINT_ENTITY, "loose-wp", 2, &[]),
+            path_entity(FINISH_ENTITY, "finish", 3, &[]),
+        ];
+        let graph = path_graph(&entities, to_world).unwrap();
+        assert_eq!(graph.spawns, vec![0, 2]);
+        assert_eq!(graph.nodes[0].next, vec![1]);
+        assert_eq!(graph.nodes[1].next, vec![4]);
+        assert_eq!(graph.nodes[2].next, vec![3]);
+        assert_eq!(graph.nodes[3].next, vec![4]);
+        assert!(graph.is_finish(4));
+    }
+
+    #[test]
+    fn rejects_files_from_before_1_0() {
+        match Root::from_file(&fixture("ldtk_0_9_3.ldtk")) {
+            Err(MapLoadError::UnsupportedJsonVersion(v)) => assert_eq!(v, "0.9.3"),
+            other => panic!("expected an unsupported version error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn checks_the_json_version_range() {
+        let load = |json: &str| Root::from_json(json);
+        assert!(matches!(load(r#"{"jsonVersion": "2.0.0"}"#), Err(MapLoadError::UnsupportedJsonVersion(_))));
+        assert!(matches!(load(r#"{"levels": []}"#), Err(MapLoadError::UnsupportedJsonVersion(_))));
+        // Newer 1.x files only log a warning
+        assert!(load(r#"{"jsonVersion": "1.9.0", "levels": []}"#).is_ok());
+        // Unknown fields are skipped and missing ones fall back to their defaults
+        let root = load(r#"{"jsonVersion": "1.2.0", "somethingNew": {"a": 1}, "levels": [{"identifier": "A", "layerInstances": null}]}"#).unwrap();
+        assert_eq!(root.level_identifiers(), vec!["A"]);
+        assert!(root.levels[0].layer_instances.is_empty());
+    }
+
+    #[test]
+    fn parses_json_versions() {
+        assert_eq!(JsonVersion::parse("1.2.3"), Some(JsonVersion(1, 2, 3)));
+        assert_eq!(JsonVersion::parse("1.5.3-beta"), Some(JsonVersion(1, 5, 3)));
+        assert_eq!(JsonVersion::parse("1"), Some(JsonVersion(1, 0, 0)));
+        assert_eq!(JsonVersion::parse(""), None);
+        assert_eq!(JsonVersion::parse("x.1"), None);
+        assert!(JsonVersion(1, 10, 0) > MAX_TESTED_JSON_VERSION);
+    }
+}
@@ -1,7 +1,27 @@
 mod buttons;
 mod map_loader;
 mod ground_mesh;
+mod cell_grid;
+mod path_graph;
+mod flow_field;
+mod ldtk_loader;
+mod level_validator;
+mod field_value;
+mod wave_set;
+mod enemy_kinds;
+mod tower_catalogue;
+mod intercept;
 
 pub use buttons::*;
 pub use map_loader::*;
-pub use ground_mesh::*;
\ No newline at end of file
+pub use ground_mesh::*;
+pub use cell_grid::*;
+pub use path_graph::*;
+pub use flow_field::*;
+pub use ldtk_loader::*;
+pub use level_validator::*;
+pub use field_value::*;
+pub use wave_set::*;
+pub use enemy_kinds::*;
+pub use tower_catalogue::*;
+pub use intercept::*;
\ No newline at end of file
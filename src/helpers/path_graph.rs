@@ -0,0 +1,281 @@
+use bevy::prelude::*;
+
+/// How an enemy standing on a fork picks the branch it takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForkChoice {
+    #[default]
+    Random,
+    /// Random, but branches with a higher weight are picked more often
+    Weighted,
+    /// Always take the branch with the least distance left to a Finish
+    ShortestRemaining,
+}
+
+impl ForkChoice {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Random" => Some(ForkChoice::Random),
+            "Weighted" => Some(ForkChoice::Weighted),
+            "Shortest" | "ShortestRemaining" => Some(ForkChoice::ShortestRemaining),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathNode {
+    /// World space (x, z) position
+    pub position: Vec2,
+    /// Nodes enemies can walk to from here, more than one makes this a fork
+    pub next: Vec<usize>,
+    /// How likely a weighted fork picks this node, relative to the other branches
+    pub weight: f32,
+    pub fork_choice: ForkChoice,
+    /// Enemies that get here have made it through
+    pub is_finish: bool,
+    /// Shortest walking distance from here to a Finish
+    pub remaining: f32,
+}
+
+impl PathNode {
+    pub fn new(position: Vec2) -> Self {
+        PathNode {
+            position,
+            next: Vec::new(),
+            weight: 1.0,
+            fork_choice: ForkChoice::default(),
+            is_finish: false,
+            remaining: f32::INFINITY,
+        }
+    }
+
+    pub fn finish(position: Vec2) -> Self {
+        PathNode {
+            is_finish: true,
+            ..PathNode::new(position)
+        }
+    }
+}
+
+/// Every lane enemies can walk, from the Spawns to the Finishes.
+/// Lanes merge by pointing at the same node.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathGraph {
+    pub nodes: Vec<PathNode>,
+    /// Nodes enemies are spawned on
+    pub spawns: Vec<usize>,
+}
+
+impl PathGraph {
+    pub fn is_finish(&self, node: usize) -> bool {
+        self.nodes[node].is_finish
+    }
+
+    pub fn connect(&mut self, from: usize, to: usize) {
+        if !self.nodes[from].next.contains(&to) {
+            self.nodes[from].next.push(to);
+        }
+    }
+
+    /// Works out the distance left to the closest Finish for every node
+    pub fn compute_remaining(&mut self) {
+        let len = self.nodes.len();
+        let mut done = vec![false; len];
+        for node in &mut self.nodes {
+            node.remaining = if node.is_finish { 0.0 } else { f32::INFINITY };
+        }
+
+        // Dijkstra backwards from the Finishes, the graphs are small enough to skip the heap
+        loop {
+            let closest = (0..len)
+                .filter(|&i| !done[i] && self.nodes[i].remaining.is_finite())
+                .min_by(|&a, &b| self.nodes[a].remaining.total_cmp(&self.nodes[b].remaining));
+            let current = match closest {
+                None => break,
+                Some(i) => i,
+            };
+            done[current] = true;
+
+            for from in 0..len {
+                if self.nodes[from].next.contains(&current) {
+                    let distance = self.nodes[current].remaining + self.nodes[from].position.distance(self.nodes[current].position);
+                    if distance < self.nodes[from].remaining {
+                        self.nodes[from].remaining = distance;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Nodes that can be reached from a Spawn but never lead to a Finish, lanes that stop on a Waypoint included.
+    /// Only meaningful after [PathGraph::compute_remaining].
+    pub fn dead_ends(&self) -> Vec<usize> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut open = self.spawns.clone();
+        while let Some(node) = open.pop() {
+            if std::mem::replace(&mut seen[node], true) {
+                continue;
+            }
+            open.extend(self.nodes[node].next.iter().copied());
+        }
+        (0..self.nodes.len())
+            .filter(|&i| seen[i] && !self.nodes[i].remaining.is_finite())
+            .collect()
+    }
+
+    /// Picks the node to walk to after reaching `node`, `roll` is a random number in [0, 1).
+    /// Returns None once a Finish has been reached.
+    pub fn choose_next(&self, node: usize, roll: f32) -> Option<usize> {
+        // Never walk into a dead end
+        let branches: Vec<usize> = self.nodes[node].next
+            .iter()
+            .copied()
+            .filter(|&n| self.nodes[n].remaining.is_finite())
+            .collect();
+        if branches.len() <= 1 {
+            return branches.first().copied();
+        }
+
+        match self.nodes[node].fork_choice {
+            ForkChoice::Random => Some(branches[((roll * branches.len() as f32) as usize).min(branches.len() - 1)]),
+            ForkChoice::Weighted => {
+                let total: f32 = branches.iter().map(|&n| self.nodes[n].weight.max(0.0)).sum();
+                let mut pick = roll * total;
+                for &n in &branches {
+                    pick -= self.nodes[n].weight.max(0.0);
+                    if pick < 0.0 {
+                        return Some(n);
+                    }
+                }
+                branches.last().copied()
+            }
+            ForkChoice::ShortestRemaining => branches
+                .iter()
+                .copied()
+                .min_by(|&a, &b| {
+                    let to_a = self.nodes[node].position.distance(self.nodes[a].position) + self.nodes[a].remaining;
+                    let to_b = self.nodes[node].position.distance(self.nodes[b].position) + self.nodes[b].remaining;
+                    to_a.total_cmp(&to_b)
+                }),
+        }
+    }
+
+    /// Every connection between two nodes, as (from, to) positions
+    pub fn segments(&self) -> impl Iterator<Item=(Vec2, Vec2)> + '_ {
+        self.nodes
+            .iter()
+            .flat_map(move |n| n.next.iter().map(move |&to| (n.position, self.nodes[to].position)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a graph from node positions, marked Finishes and (from, to) connections, with spawn 0
+    fn graph(positions: &[(f32, f32)], finishes: &[usize], edges: &[(usize, usize)]) -> PathGraph {
+        let mut graph = PathGraph {
+            nodes: positions
+                .iter()
+                .enumerate()
+                .map(|(i, &(x, y))| match finishes.contains(&i) {
+                    true => PathNode::finish(Vec2::new(x, y)),
+                    false => PathNode::new(Vec2::new(x, y)),
+                })
+                .collect(),
+            spawns: vec![0],
+        };
+        for &(from, to) in edges {
+            graph.connect(from, to);
+        }
+        graph.compute_remaining();
+        graph
+    }
+
+    /// Spawn 0 forks at 1 into a short lane through 2 and a long one through 3, both ending on Finish 4
+    fn fork() -> PathGraph {
+        graph(
+            &[(0.0, 0.0), (2.0, 0.0), (4.0, 0.0), (2.0, 6.0), (6.0, 0.0)],
+            &[4],
+            &[(0, 1), (1, 2), (1, 3), (2, 4), (3, 4)],
+        )
+    }
+
+    #[test]
+    fn remaining_is_the_shortest_walk_to_a_finish() {
+        let graph = fork();
+        assert_eq!(graph.nodes[4].remaining, 0.0);
+        assert_eq!(graph.nodes[2].remaining, 2.0);
+        assert_eq!(graph.nodes[1].remaining, 4.0);
+        assert_eq!(graph.nodes[0].remaining, 6.0);
+        assert!(graph.dead_ends().is_empty());
+    }
+
+    #[test]
+    fn a_lane_ending_on_a_waypoint_is_a_dead_end() {
+        // 2 has nowhere to go, but it is no Finish
+        let graph = graph(&[(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (4.0, 0.0)], &[3], &[(0, 1), (1, 2), (1, 3)]);
+        assert!(!graph.is_finish(2));
+        assert!(graph.nodes[2].remaining.is_infinite());
+        assert_eq!(graph.dead_ends(), vec![2]);
+        // Enemies at the fork keep out of it
+        assert_eq!(graph.choose_next(1, 0.0), Some(3));
+        assert_eq!(graph.choose_next(1, 0.99), Some(3));
+    }
+
+    #[test]
+    fn a_lane_without_any_finish_is_all_dead_ends() {
+        let graph = graph(&[(0.0, 0.0), (2.0, 0.0)], &[], &[(0, 1)]);
+        assert_eq!(graph.dead_ends(), vec![0, 1]);
+    }
+
+    #[test]
+    fn unreachable_nodes_are_no_dead_ends() {
+        let graph = graph(&[(0.0, 0.0), (2.0, 0.0), (9.0, 9.0)], &[1], &[(0, 1)]);
+        assert!(graph.dead_ends().is_empty());
+    }
+
+    #[test]
+    fn finishes_end_the_walk() {
+        let graph = fork();
+        assert_eq!(graph.choose_next(0, 0.5), Some(1));
+        assert_eq!(graph.choose_next(4, 0.5), None);
+    }
+
+    #[test]
+    fn random_forks_split_the_roll_evenly() {
+        let graph = fork();
+        assert_eq!(graph.choose_next(1, 0.0), Some(2));
+        assert_eq!(graph.choose_next(1, 0.49), Some(2));
+        assert_eq!(graph.choose_next(1, 0.5), Some(3));
+        assert_eq!(graph.choose_next(1, 0.999), Some(3));
+    }
+
+    #[test]
+    fn weighted_forks_favour_heavy_branches() {
+        let mut graph = fork();
+        graph.nodes[1].fork_choice = ForkChoice::Weighted;
+        graph.nodes[2].weight = 3.0;
+        graph.nodes[3].weight = 1.0;
+        assert_eq!(graph.choose_next(1, 0.0), Some(2));
+        assert_eq!(graph.choose_next(1, 0.74), Some(2));
+        assert_eq!(graph.choose_next(1, 0.76), Some(3));
+    }
+
+    #[test]
+    fn shortest_forks_take_the_quickest_way_out() {
+        let mut graph = fork();
+        graph.nodes[1].fork_choice = ForkChoice::ShortestRemaining;
+        for roll in [0.0, 0.5, 0.99] {
+            assert_eq!(graph.choose_next(1, roll), Some(2));
+        }
+    }
+
+    #[test]
+    fn connecting_twice_adds_one_edge() {
+        let mut graph = fork();
+        graph.connect(0, 1);
+        assert_eq!(graph.nodes[0].next, vec![1]);
+        assert_eq!(graph.segments().count(), 5);
+    }
+}
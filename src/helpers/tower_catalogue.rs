@@ -0,0 +1,175 @@
+use std::fs;
+use serde_derive::{Deserialize, Serialize};
+use crate::damage::{Damage, DamageType};
+use crate::status_effects::{StatusEffect, StatusEffectKind};
+
+/// How a tower shows its shots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectileKind {
+    #[default]
+    Bullet,
+    /// A bullet with a beam drawn to it
+    Laser,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectileDef {
+    pub kind: ProjectileKind,
+    /// Asset path of the scene to show
+    pub model: String,
+    /// World units per second
+    pub speed: f32,
+    /// Seconds before a shot that hit nothing disappears, long enough to cross the range of every tier
+    pub lifetime: f32,
+    /// Size of the hit box
+    pub size: f32,
+}
+
+impl Default for ProjectileDef {
+    fn default() -> Self {
+        ProjectileDef {
+            kind: ProjectileKind::Bullet,
+            model: "models/bullet.glb#Scene0".to_string(),
+            speed: 5.0,
+            lifetime: 1.0,
+            size: 0.2,
+        }
+    }
+}
+
+/// A status effect as written in the tower file, `strength` is the speed multiplier of a slow
+/// and the damage per second of a burn or poison
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StatusEffectDef {
+    pub kind: StatusEffectKind,
+    #[serde(default)]
+    pub strength: f32,
+    pub duration: f32,
+}
+
+impl StatusEffectDef {
+    pub fn to_effect(&self) -> StatusEffect {
+        match self.kind {
+            StatusEffectKind::Slow => StatusEffect::slow(self.strength, self.duration),
+            StatusEffectKind::Freeze => StatusEffect::freeze(self.duration),
+            StatusEffectKind::Burn => StatusEffect::burn(self.strength, self.duration),
+            StatusEffectKind::Poison => StatusEffect::poison(self.strength, self.duration),
+        }
+    }
+}
+
+/// One tier of a tower, every value left out keeps what the tier below had.
+/// The upgrades of a tower form a tree, a tier with more than one upgrade lets the player pick a specialisation.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TowerUpgradeDef {
+    pub name: String,
+    pub cost: u32,
+    pub model: Option<String>,
+    pub fire_rate: Option<f32>,
+    pub range: Option<f32>,
+    pub damage: Option<Damage>,
+    pub upgrades: Vec<TowerUpgradeDef>,
+}
+
+/// Share of what was spent on a tower that selling it pays back, unless the tower says otherwise
+pub const DEFAULT_REFUND_SHARE: f32 = 0.75;
+
+/// A tower as written in the tower data file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TowerDef {
+    /// Shown on the build menu and used to refer to the tower
+    pub name: String,
+    pub cost: u32,
+    /// Asset path of the build menu image
+    pub icon: String,
+    /// Asset path of the scene to show
+    pub model: String,
+    /// Shots per second
+    pub fire_rate: f32,
+    pub range: f32,
+    /// Where shots leave the tower, relative to its base
+    pub bullet_offset: [f32; 3],
+    pub projectile: ProjectileDef,
+    pub damage: Damage,
+    /// Left on whatever a shot hits
+    pub effect: Option<StatusEffectDef>,
+    /// Aim where targets will be when the shot gets there, the player can switch it per tower
+    pub lead_targets: bool,
+    pub upgrades: Vec<TowerUpgradeDef>,
+    /// Share of the build and upgrade costs paid back when the tower is sold
+    pub refund_share: f32,
+}
+
+impl Default for TowerDef {
+    fn default() -> Self {
+        TowerDef {
+            name: "Rock".to_string(),
+            cost: 5,
+            icon: "images/rock_tower_icon.png".to_string(),
+            model: "models/tower_1.glb#Scene0".to_string(),
+            fire_rate: 1.0,
+            range: 4.5,
+            bullet_offset: [0.0; 3],
+            projectile: ProjectileDef::default(),
+            damage: Damage::new(1.0, DamageType::Physical),
+            effect: None,
+            lead_targets: false,
+            upgrades: Vec::new(),
+            refund_share: DEFAULT_REFUND_SHARE,
+        }
+    }
+}
+
+/// Reads the list of towers of a tower data file, in build menu order
+pub fn tower_defs_from_file(path: &str) -> Result<Vec<TowerDef>, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+    serde_json::from_str(&data).map_err(|e| format!("could not parse {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrade_names(upgrades: &[TowerUpgradeDef]) -> Vec<&str> {
+        upgrades.iter().map(|u| u.name.as_str()).collect()
+    }
+
+    #[test]
+    fn loads_the_game_towers() {
+        let defs = tower_defs_from_file(&format!("{}/assets/data/towers.json", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let names: Vec<&str> = defs.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["Lazer", "Cannon", "Rock", "Frost", "Venom"]);
+
+        let lazer = &defs[0];
+        assert_eq!(lazer.projectile.kind, ProjectileKind::Laser);
+        assert_eq!(upgrade_names(&lazer.upgrades), vec!["Focused Lazer"]);
+        assert_eq!(upgrade_names(&lazer.upgrades[0].upgrades), vec!["Pulse Lazer", "Long Lazer"]);
+        assert_eq!(defs[3].effect.map(|e| e.kind), Some(StatusEffectKind::Freeze));
+        assert_eq!(defs[4].effect.map(|e| e.kind), Some(StatusEffectKind::Poison));
+
+        for def in &defs {
+            assert!(def.cost > 0 && def.fire_rate > 0.0 && def.range > 0.0, "{}", def.name);
+            let reach = def.projectile.speed * def.projectile.lifetime;
+            assert!(reach >= def.range, "{} shots expire before they leave its range", def.name);
+            let mut open: Vec<&TowerUpgradeDef> = def.upgrades.iter().collect();
+            while let Some(upgrade) = open.pop() {
+                assert!(reach >= upgrade.range.unwrap_or_default(), "{} shots expire before they leave its range", upgrade.name);
+                assert!(!upgrade.name.is_empty() && upgrade.cost > 0, "{} has an upgrade without a name or cost", def.name);
+                open.extend(&upgrade.upgrades);
+            }
+        }
+    }
+
+    #[test]
+    fn left_out_values_fall_back_to_the_defaults() {
+        let defs: Vec<TowerDef> = serde_json::from_str(r#"[{ "name": "Pebble", "upgrades": [{ "name": "Stone" }] }]"#).unwrap();
+        assert_eq!(defs[0].cost, TowerDef::default().cost);
+        assert_eq!(defs[0].projectile, ProjectileDef::default());
+        assert_eq!(defs[0].refund_share, DEFAULT_REFUND_SHARE);
+        assert_eq!(defs[0].upgrades[0].fire_rate, None);
+    }
+}
@@ -0,0 +1,77 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// Shortest time between two enemies of a group, keeps a zero spacing from spawning a pile in one frame
+pub const MIN_GROUP_SPACING: f32 = 0.05;
+
+/// A batch of identical enemies within a wave
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WaveGroup {
+    /// Kind of enemy to spawn
+    pub enemy: String,
+    pub count: u32,
+    /// Seconds between two enemies of the group
+    pub spacing: f32,
+    /// Seconds after the start of the wave before the first enemy appears
+    pub delay: f32,
+    /// Index of the Spawn to use, None takes turns between every Spawn
+    pub spawn: Option<usize>,
+}
+
+impl Default for WaveGroup {
+    fn default() -> Self {
+        WaveGroup {
+            enemy: "basic".to_string(),
+            count: 1,
+            spacing: 1.5,
+            delay: 0.0,
+            spawn: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Wave {
+    /// Seconds to wait before this wave, overriding the countdown of the WaveSet
+    pub countdown: Option<f32>,
+    /// Groups of a wave spawn at the same time, use their delay to stagger them
+    pub groups: Vec<WaveGroup>,
+}
+
+/// Every wave of a level, read from the JSON in its `waves` field:
+/// `{ "countdown": 5, "waves": [{ "groups": [{ "enemy": "basic", "count": 5, "spacing": 1.5 }] }] }`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WaveSet {
+    /// Seconds before the first wave and between two waves
+    pub countdown: f32,
+    pub waves: Vec<Wave>,
+}
+
+impl WaveSet {
+    pub fn from_json(data: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(data)
+    }
+
+    /// Used by levels without a `waves` field, a steadily growing stream of basic enemies
+    pub fn fallback() -> Self {
+        WaveSet {
+            countdown: 5.0,
+            waves: (0..10)
+                .map(|i| Wave {
+                    countdown: None,
+                    groups: vec![WaveGroup { count: 5 + i * 2, ..Default::default() }],
+                })
+                .collect(),
+        }
+    }
+
+    pub fn countdown_before(&self, wave: usize) -> f32 {
+        self.waves
+            .get(wave)
+            .and_then(|w| w.countdown)
+            .unwrap_or(self.countdown)
+            .max(0.0)
+    }
+}
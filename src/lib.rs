@@ -0,0 +1,20 @@
+pub mod tower;
+pub mod game_assets;
+pub mod target;
+pub mod bullet;
+pub mod physics;
+pub mod camera;
+pub mod ui;
+pub mod states;
+pub mod menu;
+pub mod player;
+pub mod pause;
+pub mod helpers;
+pub mod gameplay;
+pub mod weapons;
+pub mod waves;
+pub mod damage;
+pub mod status_effects;
+pub mod gameover;
+pub mod audio;
+pub mod tower_panel;
@@ -1,18 +1,3 @@
-mod tower;
-mod game_assets;
-mod target;
-mod bullet;
-mod physics;
-mod camera;
-mod ui;
-mod states;
-mod menu;
-mod player;
-mod pause;
-mod helpers;
-mod gameplay;
-mod weapons;
-
 use bevy::{
     pbr::wireframe::{WireframePlugin},
     prelude::*,
@@ -21,18 +6,23 @@ use bevy::{
 use bevy_mod_picking::*;
 use bevy_inspector_egui::WorldInspectorPlugin;
 use bevy_rapier3d::prelude::{NoUserData, RapierDebugRenderPlugin, RapierPhysicsPlugin};
-use crate::bullet::BulletPlugin;
-use crate::camera::CameraPlugin;
-use crate::game_assets::GameAssets;
-use crate::gameplay::GameplayPlugin;
-use crate::menu::MainMenuPlugin;
-use crate::pause::PauseGamePlugin;
-use crate::physics::PhysicsPlugin;
-use crate::player::PlayerPlugin;
-use crate::states::GameState;
-use crate::target::{TargetPlugin};
-use crate::tower::{TowerPlugin};
-use crate::ui::GameUiPlugin;
+use tower_power::audio::GameAudioPlugin;
+use tower_power::bullet::BulletPlugin;
+use tower_power::camera::CameraPlugin;
+use tower_power::game_assets::GameAssets;
+use tower_power::gameover::GameOverPlugin;
+use tower_power::gameplay::GameplayPlugin;
+use tower_power::menu::MainMenuPlugin;
+use tower_power::pause::PauseGamePlugin;
+use tower_power::physics::PhysicsPlugin;
+use tower_power::player::PlayerPlugin;
+use tower_power::states::GameState;
+use tower_power::status_effects::StatusEffectPlugin;
+use tower_power::target::{TargetPlugin};
+use tower_power::tower::{TowerPlugin};
+use tower_power::tower_panel::TowerPanelPlugin;
+use tower_power::ui::GameUiPlugin;
+use tower_power::waves::WavePlugin;
 
 pub const WINDOW_WIDTH: f32 = 1920.;
 pub const WINDOW_HEIGHT: f32 = 1080.0;
@@ -52,6 +42,10 @@ fn main() {
                 ..default()
             },
             ..default()
+        }).set(AssetPlugin {
+            // Lets level designers edit levels while the game is running
+            watch_for_changes: true,
+            ..default()
         }))
         .insert_resource(WgpuSettings {
             features: WgpuFeatures::POLYGON_MODE_LINE,
@@ -65,17 +59,22 @@ fn main() {
         .add_plugins(DefaultPickingPlugins)
         .add_plugin(DebugCursorPickingPlugin)
 
-        .add_state(GameState::Gameplay)
+        .add_state(GameState::Loading)
 
         .add_plugin(MainMenuPlugin)
         .add_plugin(CameraPlugin)
         .add_plugin(TowerPlugin)
         .add_plugin(BulletPlugin)
         .add_plugin(TargetPlugin)
+        .add_plugin(StatusEffectPlugin)
+        .add_plugin(WavePlugin)
         .add_plugin(PhysicsPlugin)
         .add_plugin(GameUiPlugin)
+        .add_plugin(TowerPanelPlugin)
         .add_plugin(PlayerPlugin)
         .add_plugin(PauseGamePlugin)
+        .add_plugin(GameOverPlugin)
+        .add_plugin(GameAudioPlugin)
         .add_plugin(GameplayPlugin)
 
         .add_startup_system_to_stage(StartupStage::PreStartup, asset_loading)
@@ -88,11 +87,7 @@ fn asset_loading(
     assets: Res<AssetServer>,
 ) {
     commands.insert_resource(GameAssets {
-        bullet: assets.load("models/bullet.glb#Scene0"),
         pedestal: assets.load("models/pedestal.glb#Scene0"),
-        tower: assets.load("models/tower_1.glb#Scene0"),
-        enemy: assets.load("models/enemy.glb#Scene0"),
-        mob_spawn_delay: Timer::from_seconds(1.5, TimerMode::Repeating),
         game_font: assets.load("fonts/minecraft_font.ttf"),
         enemy_death_sounds: assets.load("sounds/pop-39222.ogg"),
         tower_place_sound: assets.load("sounds/bricks-104933.ogg"),
@@ -2,6 +2,7 @@ use bevy::app::AppExit;
 use bevy::prelude::*;
 use git2::{Repository};
 use crate::game_assets::GameAssets;
+use crate::gameplay::{GameMap, MapLoadErrorMessage};
 use crate::helpers::spawn_button;
 use crate::states::GameState;
 
@@ -35,6 +36,7 @@ impl Plugin for MainMenuPlugin {
 fn spawn_main_menu(
     mut commands: Commands,
     assets: Res<GameAssets>,
+    load_error: Option<Res<MapLoadErrorMessage>>,
 ) {
     let start_button = spawn_button(&mut commands, &assets, "Start Game", Color::RED);
     commands.entity(start_button).insert(StartGameButton);
@@ -71,6 +73,26 @@ fn spawn_main_menu(
                     ),
                     ..default()
                 });
+
+            if let Some(load_error) = &load_error {
+                commands
+                    .spawn(TextBundle {
+                        style: Style {
+                            align_self: AlignSelf::Center,
+                            margin: UiRect::bottom(Val::Percent(2.0)),
+                            ..default()
+                        },
+                        text: Text::from_section(
+                            load_error.0.as_str(),
+                            TextStyle {
+                                font: assets.game_font.clone(),
+                                font_size: 24.0,
+                                color: Color::RED,
+                            },
+                        ),
+                        ..default()
+                    });
+            }
         })
         .add_child(start_button)
         .add_child(exit_button);
@@ -113,9 +135,15 @@ fn start_button_click(
     menu_root: Query<Entity, With<MenuUIRoot>>,
     mut game_state: ResMut<State<GameState>>,
     mut mouse_input: ResMut<Input<MouseButton>>,
+    map: Option<Res<GameMap>>,
 ) {
     for interaction in &interactions {
         if matches!(interaction, Interaction::Clicked) {
+            if map.is_none() {
+                warn!("No map loaded, cannot start the game");
+                continue;
+            }
+            commands.remove_resource::<MapLoadErrorMessage>();
             let root_entity = menu_root.single();
             commands.entity(root_entity).despawn_recursive();
             game_state.set(GameState::Gameplay).unwrap();
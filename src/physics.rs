@@ -1,7 +1,9 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 use crate::bullet::Bullet;
+use crate::damage::{Armour, Damage, deal_damage};
 use crate::states::GameState;
+use crate::status_effects::{StatusEffects, StatusOnHit};
 use crate::target::{Health, Target};
 
 #[derive(Bundle)]
@@ -30,11 +32,21 @@ impl PhysicsBundle {
     }
 }
 
+/// Sent for every projectile that lands on a target
+pub struct ProjectileHit {
+    /// Tower that fired the projectile
+    pub source: Option<Entity>,
+    pub target: Entity,
+    /// Health the hit took off, after armour and resistances
+    pub damage: f32,
+}
+
 pub struct PhysicsPlugin;
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_event::<ProjectileHit>()
             .add_system_set(
                 SystemSet::on_update(GameState::Gameplay)
                     .with_system(bullet_collision_detection)
@@ -43,16 +55,23 @@ impl Plugin for PhysicsPlugin {
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn bullet_collision_detection(
     mut commands: Commands,
-    bullet_query: Query<Entity, With<Bullet>>,
-    mut colliding_entities_query: Query<(&mut Health, &CollidingEntities), With<Target>>,
+    bullet_query: Query<(Entity, &Damage, Option<&StatusOnHit>, Option<&Parent>), With<Bullet>>,
+    mut colliding_entities_query: Query<(Entity, &mut Health, Option<&Armour>, &mut StatusEffects, &CollidingEntities), With<Target>>,
+    mut hits: EventWriter<ProjectileHit>,
 ) {
-    for (mut health, colliding_entities) in colliding_entities_query.iter_mut() {
-        for bullet_entity in bullet_query.iter() {
+    for (target, mut health, armour, mut status, colliding_entities) in colliding_entities_query.iter_mut() {
+        for (bullet_entity, damage, on_hit, tower) in bullet_query.iter() {
             if colliding_entities.contains(bullet_entity) {
                 commands.entity(bullet_entity).despawn_recursive();
-                health.value -= 1;
+                let dealt = deal_damage(&mut health, armour, damage);
+                // Bullets fly as children of the tower that fired them
+                hits.send(ProjectileHit { source: tower.map(Parent::get), target, damage: dealt });
+                if let Some(StatusOnHit(effect)) = on_hit {
+                    status.apply(*effect);
+                }
             }
         }
     }
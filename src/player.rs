@@ -1,7 +1,9 @@
 use bevy::prelude::*;
-use crate::gameplay::GameMap;
+use crate::gameover::GameOverEvent;
+use crate::gameplay::{GameMap, LevelEntity};
 use crate::states::GameState;
-use crate::target::TargetDeathEvent;
+use crate::target::{EnemyKilled, EnemyLeaked};
+use crate::tower::TowerSold;
 
 pub struct PlayerPlugin;
 
@@ -16,6 +18,8 @@ impl Plugin for PlayerPlugin {
             .add_system_set(
                 SystemSet::on_update(GameState::Gameplay)
                     .with_system(give_money_on_kill)
+                    .with_system(refund_sold_towers)
+                    .with_system(lose_lives_on_leak)
             )
         ;
     }
@@ -54,18 +58,16 @@ impl Player {
         None
     }
 
+    /// Takes lives off, stopping at 0. Returns the lives left, or None once there are none.
     pub fn damage(&mut self, amount: u32) -> Option<u32> {
         println!("Subtracting {} lives. Current: {}", amount, &self.lives);
-        if let Some(new_health) = self.lives.checked_sub(amount) {
-            self.lives = new_health;
-            if new_health == 0 {
-                // player dies
-                //TODO: maybe handle death logic in a different way
-                return None;
-            }
-            return Some(self.lives);
+        self.lives = self.lives.saturating_sub(amount);
+        if self.lives == 0 {
+            // player dies
+            //TODO: maybe handle death logic in a different way
+            return None;
         }
-        None
+        Some(self.lives)
     }
 }
 
@@ -73,16 +75,68 @@ fn spawn_player(
     mut commands: Commands,
     map: Res<GameMap>,
 ) {
-    commands.spawn((Player { money: map.starting_funds, lives: map.starting_lives }, Name::new("Player")));
+    commands.spawn((Player { money: map.starting_funds, lives: map.starting_lives }, Name::new("Player"), LevelEntity));
 }
 
 fn give_money_on_kill(
     mut player: Query<&mut Player>,
-    mut death_note_events: EventReader<TargetDeathEvent>,
+    mut enemy_killed: EventReader<EnemyKilled>,
 ) {
     let mut player = player.single_mut();
-    for _event in death_note_events.iter() {
-        player.add_funds(1).expect("Player overflow error on funds add");
+    for event in enemy_killed.iter() {
+        player.add_funds(event.bounty).expect("Player overflow error on funds add");
         info!("Kill! Money: {}", player.money);
     }
-}
\ No newline at end of file
+}
+
+fn refund_sold_towers(
+    mut player: Query<&mut Player>,
+    mut tower_sold: EventReader<TowerSold>,
+) {
+    let mut player = player.single_mut();
+    for event in tower_sold.iter() {
+        player.add_funds(event.refund).expect("Player overflow error on funds add");
+    }
+}
+
+fn lose_lives_on_leak(
+    mut player: Query<&mut Player>,
+    mut enemy_leaked: EventReader<EnemyLeaked>,
+    mut game_over: EventWriter<GameOverEvent>,
+) {
+    let mut player = player.single_mut();
+    for event in enemy_leaked.iter() {
+        if player.damage(event.lives_lost).is_none() {
+            // no lives left - aka dead
+            game_over.send(GameOverEvent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn damage_takes_lives_off() {
+        let mut player = Player { money: 0, lives: 10 };
+        assert_eq!(player.damage(3), Some(7));
+        assert_eq!(player.get_lives(), 7);
+    }
+
+    #[test]
+    fn losing_the_last_life_is_death() {
+        let mut player = Player { money: 0, lives: 3 };
+        assert_eq!(player.damage(3), None);
+        assert_eq!(player.get_lives(), 0);
+    }
+
+    #[test]
+    fn damage_larger_than_the_lives_left_stops_at_zero() {
+        let mut player = Player { money: 0, lives: 2 };
+        assert_eq!(player.damage(5), None);
+        assert_eq!(player.get_lives(), 0);
+        assert_eq!(player.damage(1), None);
+        assert_eq!(player.get_lives(), 0);
+    }
+}
@@ -1,5 +1,7 @@
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum GameState {
+    /// Waiting for the level files to load
+    Loading,
     MainMenu,
     Gameplay,
     Pause,
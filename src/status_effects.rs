@@ -0,0 +1,422 @@
+use std::time::Duration;
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use crate::damage::{Armour, Damage, DamageType, deal_damage};
+use crate::states::GameState;
+use crate::target::{Health, Target};
+
+/// Seconds between two ticks of damage over time
+pub const DOT_INTERVAL: f32 = 0.5;
+
+/// Most poison stacks a target can carry at once
+pub const MAX_POISON_STACKS: usize = 5;
+
+/// How high above the target the effect markers float
+const INDICATOR_HEIGHT: f32 = 0.45;
+
+pub struct StatusEffectPlugin;
+
+impl Plugin for StatusEffectPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<StatusEffectKind>()
+            .register_type::<StatusEffect>()
+            .register_type::<ActiveEffect>()
+            .register_type::<StatusEffects>()
+            .register_type::<StatusOnHit>()
+            .add_startup_system(create_status_effect_assets)
+            .add_system_set(
+                SystemSet::on_update(GameState::Gameplay)
+                    .with_system(tick_status_effects)
+                    .with_system(show_status_effects.after(tick_status_effects))
+            )
+        ;
+    }
+}
+
+/// What an effect does and how it stacks:
+/// Slow, Freeze and Burn run once, a stronger hit replaces the running effect and a weaker one only refreshes it.
+/// Poison stacks up to `MAX_POISON_STACKS` times and every stack runs out on its own.
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusEffectKind {
+    #[default]
+    Slow,
+    Freeze,
+    Burn,
+    Poison,
+}
+
+impl StatusEffectKind {
+    pub fn max_stacks(&self) -> usize {
+        match self {
+            StatusEffectKind::Poison => MAX_POISON_STACKS,
+            _ => 1,
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            StatusEffectKind::Slow => Color::rgba(0.4, 0.6, 1.0, 0.8),
+            StatusEffectKind::Freeze => Color::rgba(0.85, 0.95, 1.0, 0.9),
+            StatusEffectKind::Burn => Color::rgba(1.0, 0.45, 0.1, 0.9),
+            StatusEffectKind::Poison => Color::rgba(0.35, 0.9, 0.2, 0.8),
+        }
+    }
+}
+
+/// A timed effect as a tower hands it out
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq)]
+pub struct StatusEffect {
+    pub kind: StatusEffectKind,
+    /// Seconds the effect lasts
+    pub duration: f32,
+    /// Movement speed is multiplied by this while the effect lasts
+    pub speed_multiplier: f32,
+    /// Damage per second, dealt every `DOT_INTERVAL` seconds
+    pub damage_per_second: Damage,
+}
+
+impl StatusEffect {
+    pub fn slow(speed_multiplier: f32, duration: f32) -> Self {
+        StatusEffect {
+            kind: StatusEffectKind::Slow,
+            duration,
+            speed_multiplier: speed_multiplier.clamp(0.0, 1.0),
+            damage_per_second: Damage::default(),
+        }
+    }
+
+    pub fn freeze(duration: f32) -> Self {
+        StatusEffect {
+            kind: StatusEffectKind::Freeze,
+            duration,
+            speed_multiplier: 0.0,
+            damage_per_second: Damage::default(),
+        }
+    }
+
+    pub fn burn(damage_per_second: f32, duration: f32) -> Self {
+        StatusEffect {
+            kind: StatusEffectKind::Burn,
+            duration,
+            speed_multiplier: 1.0,
+            damage_per_second: Damage::new(damage_per_second, DamageType::Energy),
+        }
+    }
+
+    pub fn poison(damage_per_second: f32, duration: f32) -> Self {
+        StatusEffect {
+            kind: StatusEffectKind::Poison,
+            duration,
+            speed_multiplier: 1.0,
+            damage_per_second: Damage::new(damage_per_second, DamageType::Energy),
+        }
+    }
+
+    /// Slows harder or hurts more than `other`
+    pub fn is_stronger_than(&self, other: &StatusEffect) -> bool {
+        self.speed_multiplier < other.speed_multiplier || self.damage_per_second.amount > other.damage_per_second.amount
+    }
+}
+
+/// An effect running on a target
+#[derive(Reflect, FromReflect, Clone, Debug, Default)]
+pub struct ActiveEffect {
+    pub effect: StatusEffect,
+    pub remaining: f32,
+    tick: Timer,
+}
+
+impl ActiveEffect {
+    fn new(effect: StatusEffect) -> Self {
+        ActiveEffect {
+            effect,
+            remaining: effect.duration,
+            tick: Timer::from_seconds(DOT_INTERVAL, TimerMode::Repeating),
+        }
+    }
+}
+
+/// Every effect running on a target, all targets get one when they spawn
+#[derive(Component, Reflect, Clone, Debug, Default)]
+#[reflect(Component)]
+pub struct StatusEffects {
+    pub effects: Vec<ActiveEffect>,
+}
+
+impl StatusEffects {
+    /// Adds an effect following the stacking rules of its kind
+    pub fn apply(&mut self, effect: StatusEffect) {
+        let kind = effect.kind;
+        if kind.max_stacks() > 1 {
+            let stacks = self.effects.iter().filter(|e| e.effect.kind == kind).count();
+            if stacks >= kind.max_stacks() {
+                // Make room by dropping the stack closest to running out
+                if let Some(i) = self.effects
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, e)| e.effect.kind == kind)
+                    .min_by(|(_, a), (_, b)| a.remaining.total_cmp(&b.remaining))
+                    .map(|(i, _)| i) {
+                    self.effects.remove(i);
+                }
+            }
+            self.effects.push(ActiveEffect::new(effect));
+            return;
+        }
+
+        match self.effects.iter_mut().find(|e| e.effect.kind == kind) {
+            // The tick timer carries on, a stronger burn does not restart the wait for its next tick
+            Some(running) if effect.is_stronger_than(&running.effect) => {
+                running.effect = effect;
+                running.remaining = effect.duration;
+            }
+            // Never lasts longer than the running effect itself would
+            Some(running) => {
+                running.remaining = running.remaining.max(effect.duration.min(running.effect.duration));
+            }
+            None => self.effects.push(ActiveEffect::new(effect)),
+        }
+    }
+
+    /// Runs every effect down by `delta`, deals their damage over time and drops the ones that ran out
+    pub fn tick(&mut self, delta: Duration, health: &mut Health, armour: Option<&Armour>) {
+        for active in self.effects.iter_mut() {
+            let dps = active.effect.damage_per_second;
+            if dps.amount > 0.0 {
+                // A long frame must not deal ticks past the end of the effect
+                active.tick.tick(delta.min(Duration::from_secs_f32(active.remaining.max(0.0))));
+                // Only whole ticks count, an effect that runs out halfway through a tick deals nothing for it
+                let tick_damage = Damage::new(dps.amount * DOT_INTERVAL, dps.damage_type);
+                for _ in 0..active.tick.times_finished_this_tick() {
+                    deal_damage(health, armour, &tick_damage);
+                }
+            }
+            active.remaining -= delta.as_secs_f32();
+        }
+        self.effects.retain(|e| e.remaining > 0.0);
+    }
+
+    /// Product of the speed multipliers of every running effect
+    pub fn speed_multiplier(&self) -> f32 {
+        self.effects.iter().map(|e| e.effect.speed_multiplier).product::<f32>().max(0.0)
+    }
+
+    pub fn has(&self, kind: StatusEffectKind) -> bool {
+        self.effects.iter().any(|e| e.effect.kind == kind)
+    }
+
+    /// Kinds of the running effects, sorted and without duplicates
+    pub fn kinds(&self) -> Vec<StatusEffectKind> {
+        let mut kinds: Vec<StatusEffectKind> = self.effects.iter().map(|e| e.effect.kind).collect();
+        kinds.sort();
+        kinds.dedup();
+        kinds
+    }
+}
+
+/// Put on a bullet to hand its effect to whatever it hits
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct StatusOnHit(pub StatusEffect);
+
+/// Marker floating above a target while it suffers from an effect
+#[derive(Component)]
+pub struct StatusIndicator(pub StatusEffectKind);
+
+#[derive(Resource)]
+pub struct StatusEffectAssets {
+    pub mesh: Handle<Mesh>,
+    pub materials: [Handle<StandardMaterial>; 4],
+}
+
+impl StatusEffectAssets {
+    fn material(&self, kind: StatusEffectKind) -> Handle<StandardMaterial> {
+        self.materials[kind as usize].clone()
+    }
+}
+
+fn create_status_effect_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let kinds = [StatusEffectKind::Slow, StatusEffectKind::Freeze, StatusEffectKind::Burn, StatusEffectKind::Poison];
+    commands.insert_resource(StatusEffectAssets {
+        mesh: meshes.add(Mesh::from(shape::UVSphere { radius: 0.05, ..default() })),
+        materials: kinds.map(|kind| materials.add(StandardMaterial {
+            base_color: kind.color(),
+            emissive: kind.color(),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        })),
+    });
+}
+
+/// Runs the effects down, deals their damage over time and drops the ones that ran out
+fn tick_status_effects(
+    mut targets: Query<(&mut StatusEffects, &mut Health, Option<&Armour>), With<Target>>,
+    time: Res<Time>,
+) {
+    for (mut status, mut health, armour) in &mut targets {
+        if status.effects.is_empty() {
+            continue;
+        }
+        status.tick(time.delta(), &mut health, armour);
+    }
+}
+
+/// Keeps one marker per running kind of effect above each target
+fn show_status_effects(
+    mut commands: Commands,
+    assets: Res<StatusEffectAssets>,
+    targets: Query<(Entity, &StatusEffects, Option<&Children>), Changed<StatusEffects>>,
+    indicators: Query<&StatusIndicator>,
+) {
+    for (entity, status, children) in &targets {
+        let kinds = status.kinds();
+        let shown: Vec<(Entity, StatusEffectKind)> = children
+            .into_iter()
+            .flatten()
+            .filter_map(|&child| indicators.get(child).ok().map(|i| (child, i.0)))
+            .collect();
+        if shown.iter().map(|(_, kind)| *kind).eq(kinds.iter().copied()) {
+            continue;
+        }
+
+        for (indicator, _) in shown {
+            commands.entity(indicator).despawn_recursive();
+        }
+        commands.entity(entity).with_children(|commands| {
+            let offset = (kinds.len() as f32 - 1.0) / 2.0;
+            for (i, kind) in kinds.into_iter().enumerate() {
+                commands.spawn(PbrBundle {
+                    mesh: assets.mesh.clone(),
+                    material: assets.material(kind),
+                    transform: Transform::from_xyz((i as f32 - offset) * 0.12, INDICATOR_HEIGHT, 0.0),
+                    ..default()
+                })
+                    .insert(NotShadowCaster)
+                    .insert((StatusIndicator(kind), Name::new(format!("Status_{:?}", kind))));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::damage::Resistances;
+
+    fn health() -> Health {
+        Health { value: 10.0, max: 10.0 }
+    }
+
+    fn secs(seconds: f32) -> Duration {
+        Duration::from_secs_f32(seconds)
+    }
+
+    #[test]
+    fn a_stronger_hit_replaces_the_running_effect_whole() {
+        let mut status = StatusEffects::default();
+        status.apply(StatusEffect::slow(0.8, 5.0));
+        status.apply(StatusEffect::slow(0.5, 1.0));
+        assert_eq!(status.effects.len(), 1);
+        assert_eq!(status.effects[0].effect, StatusEffect::slow(0.5, 1.0));
+        assert_eq!(status.effects[0].remaining, 1.0);
+    }
+
+    #[test]
+    fn a_weaker_hit_only_refreshes_the_running_effect() {
+        let mut status = StatusEffects::default();
+        status.apply(StatusEffect::slow(0.5, 2.0));
+        status.tick(secs(1.5), &mut health(), None);
+        status.apply(StatusEffect::slow(0.8, 5.0));
+        assert_eq!(status.effects[0].effect, StatusEffect::slow(0.5, 2.0));
+        // Back to full, but no longer than the running slow lasts
+        assert_eq!(status.effects[0].remaining, 2.0);
+
+        // A short hit does not cut a long effect short
+        status.apply(StatusEffect::slow(0.5, 0.5));
+        assert_eq!(status.effects[0].remaining, 2.0);
+    }
+
+    #[test]
+    fn poison_stacks_up_to_the_limit() {
+        let mut status = StatusEffects::default();
+        for i in 0..MAX_POISON_STACKS {
+            status.apply(StatusEffect::poison(1.0, 2.0 + i as f32));
+        }
+        assert_eq!(status.effects.len(), MAX_POISON_STACKS);
+
+        // The stack closest to running out makes room
+        status.apply(StatusEffect::poison(1.0, 10.0));
+        assert_eq!(status.effects.len(), MAX_POISON_STACKS);
+        assert!(status.effects.iter().all(|e| e.remaining > 2.0));
+    }
+
+    #[test]
+    fn speed_multipliers_combine() {
+        let mut status = StatusEffects::default();
+        assert_eq!(status.speed_multiplier(), 1.0);
+        status.apply(StatusEffect::slow(0.5, 2.0));
+        status.apply(StatusEffect::burn(1.0, 2.0));
+        assert_eq!(status.speed_multiplier(), 0.5);
+        status.apply(StatusEffect::freeze(1.0));
+        assert_eq!(status.speed_multiplier(), 0.0);
+        assert_eq!(status.kinds(), vec![StatusEffectKind::Slow, StatusEffectKind::Freeze, StatusEffectKind::Burn]);
+    }
+
+    #[test]
+    fn damage_over_time_is_dealt_in_whole_ticks() {
+        let mut status = StatusEffects::default();
+        let mut health = health();
+        status.apply(StatusEffect::burn(2.0, 1.2));
+
+        status.tick(secs(0.4), &mut health, None);
+        assert_eq!(health.value, 10.0);
+        status.tick(secs(0.2), &mut health, None);
+        assert_eq!(health.value, 9.0);
+        // The tick at 1.0 is dealt, the burn runs out at 1.2 before the next one
+        status.tick(secs(0.7), &mut health, None);
+        assert_eq!(health.value, 8.0);
+        assert!(status.effects.is_empty());
+    }
+
+    #[test]
+    fn a_long_frame_only_deals_the_ticks_left_in_the_effect() {
+        let mut status = StatusEffects::default();
+        let mut health = health();
+        status.apply(StatusEffect::burn(2.0, 1.2));
+
+        // Only the ticks at 0.5 and 1.0 are dealt out of 5 seconds
+        status.tick(secs(5.0), &mut health, None);
+        assert_eq!(health.value, 8.0);
+        assert!(status.effects.is_empty());
+    }
+
+    #[test]
+    fn damage_over_time_goes_through_armour() {
+        let mut status = StatusEffects::default();
+        let mut health = health();
+        let armour = Armour { value: 0.0, resistances: Resistances { energy: 0.5, ..Default::default() } };
+        status.apply(StatusEffect::poison(2.0, 1.0));
+        status.apply(StatusEffect::poison(2.0, 1.0));
+        status.tick(secs(0.5), &mut health, Some(&armour));
+        assert_eq!(health.value, 9.0);
+    }
+
+    #[test]
+    fn effects_run_out() {
+        let mut status = StatusEffects::default();
+        status.apply(StatusEffect::slow(0.5, 1.0));
+        status.tick(secs(0.9), &mut health(), None);
+        assert!(status.has(StatusEffectKind::Slow));
+        status.tick(secs(0.2), &mut health(), None);
+        assert!(!status.has(StatusEffectKind::Slow));
+        assert_eq!(status.speed_multiplier(), 1.0);
+    }
+}
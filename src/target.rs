@@ -1,13 +1,35 @@
 use bevy::math::Vec3Swizzles;
 use bevy::pbr::NotShadowCaster;
 use bevy::prelude::*;
-use crate::game_assets::GameAssets;
-use crate::gameplay::GameMap;
-use crate::physics::PhysicsBundle;
-use crate::player::Player;
+use bevy::utils::HashMap;
+use rand::Rng;
+use crate::damage::{Armour, Resistances};
+use crate::gameplay::{GameMap, LevelEntity, LevelReloadedEvent, reload_campaign};
+use crate::helpers::{DEFAULT_ENEMY_KIND, EnemyKindDef, enemy_kinds_from_file};
+use crate::physics::{PhysicsBundle, ProjectileHit};
 use crate::states::GameState;
+use crate::status_effects::StatusEffects;
 
-pub struct TargetDeathEvent;
+/// Sent when an enemy runs out of health, right before it is despawned
+pub struct EnemyKilled {
+    pub entity: Entity,
+    pub kind: String,
+    pub position: Vec3,
+    pub bounty: u32,
+    /// Tower that hit it last, damage over time may have finished it off since
+    pub killer: Option<Entity>,
+}
+
+/// Sent when an enemy walks into a Finish
+pub struct EnemyLeaked {
+    pub kind: String,
+    pub lives_lost: u32,
+}
+
+pub const ENEMY_KINDS_FILE: &str = "./assets/data/enemies.json";
+
+/// How high above the terrain targets float
+pub const TARGET_HOVER_HEIGHT: f32 = 0.1;
 
 pub struct TargetPlugin;
 
@@ -16,61 +38,241 @@ impl Plugin for TargetPlugin {
         app
             .register_type::<Target>()
             .register_type::<Health>()
+            .register_type::<Armour>()
+            .register_type::<Resistances>()
+            .register_type::<MovementMode>()
 
-            .add_event::<TargetDeathEvent>()
+            .add_event::<EnemyKilled>()
+            .add_event::<EnemyLeaked>()
+            .add_startup_system_to_stage(StartupStage::PreStartup, load_enemy_kinds)
             .add_system_set(SystemSet::on_enter(GameState::Gameplay)
                 .with_system(show_waypoints)
             )
             .add_system_set(
                 SystemSet::on_update(GameState::Gameplay)
-                    .with_system(spawn_targets)
-                    .with_system(move_targets)
-                    .with_system(target_death)
+                    .with_system(move_targets.after(rebuild_waypoints))
+                    .with_system(remember_last_hit)
+                    .with_system(target_death.after(remember_last_hit))
                     .with_system(check_waypoints.after(move_targets))
             )
+            .add_system(rebuild_waypoints.after(reload_campaign))
         ;
     }
 }
 
+/// How a target finds its way to a Finish
+#[derive(Reflect, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovementMode {
+    /// Walk the path graph of hand placed waypoints
+    #[default]
+    Waypoints,
+    /// Follow the flow field over the walkable grid cells
+    Pathfinding,
+}
+
+/// Stats and model of one kind of enemy
+#[derive(Debug, Clone)]
+pub struct EnemyKind {
+    pub name: String,
+    pub max_health: f32,
+    pub armour: Armour,
+    pub speed: f32,
+    pub bounty: u32,
+    pub lives_cost: u32,
+    pub collider: Vec3,
+    pub model: Handle<Scene>,
+    pub scale: f32,
+}
+
+/// Every kind of enemy waves can send, by name
+#[derive(Resource, Default)]
+pub struct EnemyRegistry {
+    kinds: HashMap<String, EnemyKind>,
+}
+
+impl EnemyRegistry {
+    pub fn get(&self, kind: &str) -> Option<&EnemyKind> {
+        self.kinds.get(kind)
+    }
+
+    /// The kind with this name, or the default kind for names nobody defined
+    pub fn get_or_default(&self, kind: &str) -> &EnemyKind {
+        self.get(kind)
+            .or_else(|| self.get(DEFAULT_ENEMY_KIND))
+            .expect("the default enemy kind is always registered")
+    }
+}
+
 #[derive(Component, Reflect, Default)]
 #[reflect(Component)]
 pub struct Target {
+    /// Name of the EnemyKind in the registry
+    pub kind: String,
     pub speed: f32,
+    pub mode: MovementMode,
+    /// Path graph node the target is walking towards, when following waypoints
+    pub node: usize,
+    /// Grid cell the target is walking towards, when pathfinding
+    pub cell: UVec2,
+    /// How many waypoints the target has passed
     pub path_index: usize,
+    /// Set once the target walked into a Finish
+    pub finished: bool,
+    /// Tower whose projectile hit the target last
+    #[reflect(ignore)]
+    pub last_hit_by: Option<Entity>,
+}
+
+impl Target {
+    /// Shortest walking distance left to a Finish, through the waypoint or cell the target is heading for.
+    /// Infinite when it can not get to one from there.
+    pub fn distance_left(&self, position: Vec2, path: &GameMap) -> f32 {
+        match self.mode {
+            MovementMode::Waypoints => path.paths.nodes
+                .get(self.node)
+                .map_or(f32::INFINITY, |node| node.remaining + node.position.distance(position)),
+            MovementMode::Pathfinding => path.flow_field
+                .distance(self.cell)
+                .map_or(f32::INFINITY, |cells| {
+                    (cells * path.grid_size) as f32 + path.cell_center(self.cell).distance(position)
+                }),
+        }
+    }
+
+    /// Carries on walking a reloaded map from `position`: with the movement of the new map, heading for
+    /// the closest node that still leads to a Finish or for the cell it is standing on
+    pub fn repoint(&mut self, position: Vec2, path: &GameMap) {
+        self.mode = path.movement;
+        let closest = path.paths.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.remaining.is_finite())
+            .min_by(|(_, a), (_, b)| a.position.distance(position).total_cmp(&b.position.distance(position)));
+        self.node = match closest {
+            Some((node, _)) => node,
+            // Nowhere left to go, keep the node in range so it can still be looked up
+            None => self.node.min(path.paths.nodes.len().saturating_sub(1)),
+        };
+        if let Some(cell) = path.world_to_cell(position) {
+            self.cell = cell;
+        }
+    }
+
+    /// How far along its path the target is, higher is closer to a Finish.
+    /// Ranks by the distance left, so a target on a detour or a longer branch counts as further behind.
+    pub fn progress(&self, position: Vec2, path: &GameMap) -> f32 {
+        -self.distance_left(position, path)
+    }
+
+    /// The next points the target walks to, as far as they can be known: the one it is heading for,
+    /// and the one after it unless the target has to pick a branch there.
+    pub fn route_ahead(&self, path: &GameMap) -> Vec<Vec2> {
+        match self.mode {
+            MovementMode::Waypoints => {
+                let node = match path.paths.nodes.get(self.node) {
+                    Some(node) => node,
+                    None => return Vec::new(),
+                };
+                let mut route = vec![node.position];
+                if let [next] = node.next.as_slice() {
+                    route.push(path.paths.nodes[*next].position);
+                }
+                route
+            }
+            MovementMode::Pathfinding => {
+                let mut route = vec![path.cell_center(self.cell)];
+                if let Some(next) = path.flow_field.next_cell(self.cell) {
+                    route.push(path.cell_center(next));
+                }
+                route
+            }
+        }
+    }
 }
 
 #[derive(Component, Reflect, Default)]
 #[reflect(Component)]
 pub struct Health {
-    pub value: i32,
+    pub value: f32,
+    pub max: f32,
 }
 
+/// Parent of the waypoint and lane markers
 #[derive(Component)]
 pub struct Waypoints;
 
 #[derive(Component)]
 pub struct Movable;
 
+#[allow(clippy::type_complexity)]
 fn move_targets(
-    mut targets: Query<(&mut Target, &mut Transform), (With<Health>, With<Movable>)>,
+    mut targets: Query<(&mut Target, &mut Transform, Option<&StatusEffects>), (With<Health>, With<Movable>)>,
     path: Res<GameMap>,
     time: Res<Time>,
 ) {
-    for (mut target, mut transform) in &mut targets {
-        let delta = target.speed * time.delta_seconds();
-        let delta_target = path.waypoints[target.path_index] - transform.translation.xz();
+    let mut rng = rand::thread_rng();
+    for (mut target, mut transform, status) in &mut targets {
+        if target.finished {
+            continue;
+        }
+        if target.mode == MovementMode::Pathfinding && path.flow_field.distance(target.cell).is_none() {
+            // The cell we were heading for got blocked, reroute from where we are
+            if let Some(here) = path.world_to_cell(transform.translation.xz()) {
+                target.cell = path.flow_field.next_cell(here).unwrap_or(here);
+            }
+        }
+
+        let speed = target.speed * status.map_or(1.0, StatusEffects::speed_multiplier);
+        let delta = speed * time.delta_seconds();
+        if delta <= 0.0 {
+            // Frozen in place
+            continue;
+        }
+        let waypoint = match target.mode {
+            MovementMode::Waypoints => match path.paths.nodes.get(target.node) {
+                Some(node) => node.position,
+                None => {
+                    // The path graph shrunk under us, carry on from the closest node
+                    target.repoint(transform.translation.xz(), &path);
+                    match path.paths.nodes.get(target.node) {
+                        Some(node) => node.position,
+                        None => continue,
+                    }
+                }
+            },
+            MovementMode::Pathfinding => path.cell_center(target.cell),
+        };
+        let delta_target = waypoint - transform.translation.xz();
 
         if delta_target.length() > delta {
 
             // we are still some way off the target, look at the target and yeet yourself that way
             let movement = delta_target.normalize() * delta;
             transform.translation += movement.extend(0.0).xzy();
+            // Walk up and down raised terrain
+            transform.translation.y = path.height_at(transform.translation.xz()) + TARGET_HOVER_HEIGHT;
             let y = transform.translation.y;
-            transform.look_at(path.waypoints[target.path_index].extend(y).xzy(), Vec3::Y);
+            transform.look_at(waypoint.extend(y).xzy(), Vec3::Y);
         } else {
-            // we have reached the target, increment the index
-            // TODO: Reached end of path, maybe emit an event that we are done
-            target.path_index += 1;
+            // we have reached the target, pick where to go next
+            match target.mode {
+                MovementMode::Waypoints => {
+                    target.path_index += 1;
+                    match path.paths.choose_next(target.node, rng.gen()) {
+                        Some(next) => target.node = next,
+                        None => target.finished = true,
+                    }
+                }
+                MovementMode::Pathfinding => {
+                    if path.flow_field.is_goal(target.cell) {
+                        target.finished = true;
+                    } else if let Some(next) = path.flow_field.next_cell(target.cell) {
+                        target.path_index += 1;
+                        target.cell = next;
+                    }
+                    // Otherwise there is no way to a Finish from here, wait for the walkable cells to change
+                }
+            }
         }
     }
 }
@@ -81,15 +283,55 @@ fn show_waypoints(
     mut materials: ResMut<Assets<StandardMaterial>>,
     path: Res<GameMap>,
 ) {
+    spawn_waypoints(&mut commands, &mut meshes, &mut materials, &path);
+}
+
+/// Puts the markers of a reloaded level in place and sends walking targets to the closest node of the new paths
+fn rebuild_waypoints(
+    mut commands: Commands,
+    mut level_reloaded: EventReader<LevelReloadedEvent>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    path: Res<GameMap>,
+    waypoints: Query<Entity, With<Waypoints>>,
+    mut targets: Query<(&mut Target, &Transform)>,
+) {
+    if level_reloaded.iter().last().is_none() {
+        return;
+    }
+
+    // Only a level that is being played has markers to replace
+    if !waypoints.is_empty() {
+        for entity in &waypoints {
+            commands.entity(entity).despawn_recursive();
+        }
+        spawn_waypoints(&mut commands, &mut meshes, &mut materials, &path);
+    }
+
+    for (mut target, transform) in &mut targets {
+        target.repoint(transform.translation.xz(), &path);
+    }
+}
+
+fn spawn_waypoints(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    path: &GameMap,
+) {
+    let waypoint_mesh = meshes.add(Mesh::from(shape::Cube { size: 0.2 }));
+    let lane_mesh = meshes.add(Mesh::from(shape::Box::new(0.05, 0.05, 1.0)));
+    let material = materials.add(Color::rgba(1., 0.063, 0.941, 0.65).into());
+
     commands.spawn(SpatialBundle {
         ..default()
     }).with_children(|commands| {
-        for wp in &path.waypoints {
+        for wp in path.paths.nodes.iter().map(|n| n.position) {
             commands.spawn(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Cube { size: 0.2 })),
-                material: materials.add(Color::rgba(1., 0.063, 0.941, 0.65).into()),
+                mesh: waypoint_mesh.clone(),
+                material: material.clone(),
                 transform: Transform {
-                    translation: Vec3::new(wp.x, 0., wp.y),
+                    translation: Vec3::new(wp.x, path.height_at(wp), wp.y),
                     ..default()
                 },
                 ..default()
@@ -97,40 +339,118 @@ fn show_waypoints(
                 .insert(NotShadowCaster)
                 .insert(Name::new(format!("waypoint_{}_{}", wp.x, wp.y)));
         }
-    }).insert(Name::new("waypoints"));
+
+        // A thin bar along every lane, forks and merges included
+        for (from, to) in path.paths.segments() {
+            let start = from.extend(path.height_at(from)).xzy();
+            let end = to.extend(path.height_at(to)).xzy();
+            commands.spawn(PbrBundle {
+                mesh: lane_mesh.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation((start + end) / 2.0)
+                    .looking_at(end, Vec3::Y)
+                    .with_scale(Vec3::new(1.0, 1.0, start.distance(end))),
+                ..default()
+            })
+                .insert(NotShadowCaster)
+                .insert(Name::new(format!("lane_{}_{}_{}_{}", from.x, from.y, to.x, to.y)));
+        }
+    }).insert((Name::new("waypoints"), Waypoints, LevelEntity));
 }
 
-fn spawn_targets(
+fn load_enemy_kinds(
     mut commands: Commands,
-    mut assets: ResMut<GameAssets>,
-    time: Res<Time>,
-    path: Res<GameMap>,
+    asset_server: Res<AssetServer>,
 ) {
-    let spawn = Vec3::new(path.waypoints[0].x, 0.1, path.waypoints[0].y);
-    assets.mob_spawn_delay.tick(time.delta());
-    if assets.mob_spawn_delay.just_finished() {
-        commands.spawn(SceneBundle {
-            scene: assets.enemy.clone(),
-            transform: Transform::from_xyz(spawn.x, spawn.y, spawn.z),
-            ..default()
+    let mut defs = enemy_kinds_from_file(ENEMY_KINDS_FILE).unwrap_or_else(|e| {
+        error!("Failed to load enemy kinds! {}", e);
+        Default::default()
+    });
+    if !defs.contains_key(DEFAULT_ENEMY_KIND) {
+        warn!("{} has no {} enemy, using the built in one", ENEMY_KINDS_FILE, DEFAULT_ENEMY_KIND);
+        defs.insert(DEFAULT_ENEMY_KIND.to_string(), EnemyKindDef::default());
+    }
+
+    let kinds = defs
+        .into_iter()
+        .map(|(name, def)| {
+            let kind = EnemyKind {
+                name: name.clone(),
+                max_health: def.max_health,
+                armour: Armour { value: def.armour, resistances: def.resistances },
+                speed: def.speed,
+                bounty: def.bounty,
+                lives_cost: def.lives_cost,
+                collider: Vec3::from(def.collider),
+                model: asset_server.load(def.model.as_str()),
+                scale: def.scale,
+            };
+            (name, kind)
+        })
+        .collect();
+    commands.insert_resource(EnemyRegistry { kinds });
+}
+
+/// Puts a new target of the given kind on the Spawn path node `spawn`
+pub fn spawn_target(
+    commands: &mut Commands,
+    kind: &EnemyKind,
+    path: &GameMap,
+    spawn: usize,
+) -> Entity {
+    let start = path.paths.nodes[spawn].position;
+    let position = start.extend(path.height_at(start) + TARGET_HOVER_HEIGHT).xzy();
+    commands.spawn(SceneBundle {
+        scene: kind.model.clone(),
+        transform: Transform::from_translation(position).with_scale(Vec3::splat(kind.scale)),
+        ..default()
+    })
+        .insert(Movable)
+        .insert(Target {
+            kind: kind.name.clone(),
+            speed: kind.speed,
+            mode: path.movement,
+            node: spawn,
+            cell: path.world_to_cell(start).unwrap_or_default(),
+            path_index: 0,
+            finished: false,
+            last_hit_by: None,
         })
-            .insert(Movable)
-            .insert(Target { speed: 1.4, path_index: 0 })
-            .insert(Health { value: 4 })
-            .insert(PhysicsBundle::moving_entity(Vec3::new(0.24, 0.24, 0.1)))
-            .insert(Name::new("Target"));
+        .insert(Health { value: kind.max_health, max: kind.max_health })
+        .insert(kind.armour)
+        .insert(StatusEffects::default())
+        .insert(PhysicsBundle::moving_entity(kind.collider))
+        .insert((Name::new(format!("Target_{}", kind.name)), LevelEntity))
+        .id()
+}
+
+fn remember_last_hit(
+    mut hits: EventReader<ProjectileHit>,
+    mut targets: Query<&mut Target>,
+) {
+    for hit in hits.iter() {
+        if let Ok(mut target) = targets.get_mut(hit.target) {
+            target.last_hit_by = hit.source;
+        }
     }
 }
 
 fn target_death(
     mut commands: Commands,
-    targets: Query<(Entity, &Health), With<Target>>,
-    mut death_note: EventWriter<TargetDeathEvent>,
+    targets: Query<(Entity, &Health, &Target, &GlobalTransform)>,
+    mut enemy_killed: EventWriter<EnemyKilled>,
+    enemies: Res<EnemyRegistry>,
 ) {
-    for (target, health) in &targets {
-        if health.value <= 0 {
-            death_note.send(TargetDeathEvent);
-            commands.entity(target).despawn_recursive();
+    for (entity, health, target, transform) in &targets {
+        if health.value <= 0.0 {
+            enemy_killed.send(EnemyKilled {
+                entity,
+                kind: target.kind.clone(),
+                position: transform.translation(),
+                bounty: enemies.get_or_default(&target.kind).bounty,
+                killer: target.last_hit_by,
+            });
+            commands.entity(entity).despawn_recursive();
         }
     }
 }
@@ -138,23 +458,138 @@ fn target_death(
 fn check_waypoints(
     mut commands: Commands,
     targets: Query<(Entity, &Target)>,
-    path: Res<GameMap>,
-    mut player: Query<&mut Player>,
-    audio: Res<Audio>,
-    assets: Res<GameAssets>,
+    mut enemy_leaked: EventWriter<EnemyLeaked>,
+    enemies: Res<EnemyRegistry>,
 ) {
     for (entity, target) in &targets {
-        if target.path_index >= path.waypoints.len() {
-            // Maybe do this via an event system
+        if target.finished {
             // we reached the end
-            audio.play(assets.enemy_death_sounds.clone());
-
             commands.entity(entity).despawn_recursive();
-            let mut player = player.single_mut();
-            if player.damage(1).is_none() {
-                // we returned no lives, means we are at 0 or under lives - aka dead
-                info!("GAME OVER"); //TODO: Do something better
-            }
+            enemy_leaked.send(EnemyLeaked {
+                kind: target.kind.clone(),
+                lives_lost: enemies.get_or_default(&target.kind).lives_cost,
+            });
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::{CellGrid, CellType, FlowField, PathGraph, PathNode};
+
+    /// Spawn 0 forks at 1 into a straight lane through 2 and a winding one through 3 and 4, both ending on Finish 5
+    fn forked_map() -> GameMap {
+        let mut paths = PathGraph {
+            nodes: vec![
+                PathNode::new(Vec2::new(0.0, 0.0)),
+                PathNode::new(Vec2::new(2.0, 0.0)),
+                PathNode::new(Vec2::new(6.0, 0.0)),
+                PathNode::new(Vec2::new(2.0, 8.0)),
+                PathNode::new(Vec2::new(10.0, 8.0)),
+                PathNode::finish(Vec2::new(10.0, 0.0)),
+            ],
+            spawns: vec![0],
+        };
+        for (from, to) in [(0, 1), (1, 2), (2, 5), (1, 3), (3, 4), (4, 5)] {
+            paths.connect(from, to);
         }
+        paths.compute_remaining();
+        GameMap { paths, ..Default::default() }
+    }
+
+    /// A single row of 6 walkable cells of size 2 leading to a Finish in the last one
+    fn row_map() -> GameMap {
+        GameMap {
+            grid_size: 2,
+            movement: MovementMode::Pathfinding,
+            cells: CellGrid { width: 6, height: 1, cells: vec![CellType::Path; 6], ..Default::default() },
+            flow_field: FlowField::compute(6, 1, &[UVec2::new(5, 0)], |_| true),
+            ..Default::default()
+        }
+    }
+
+    fn walking_to(node: usize, path_index: usize) -> Target {
+        Target { node, path_index, ..Default::default() }
+    }
+
+    #[test]
+    fn distance_left_follows_the_waypoints() {
+        let map = forked_map();
+        let target = walking_to(2, 2);
+        assert_eq!(target.distance_left(Vec2::new(4.0, 0.0), &map), 6.0);
+        let target = walking_to(4, 3);
+        assert_eq!(target.distance_left(Vec2::new(6.0, 8.0), &map), 12.0);
+    }
+
+    #[test]
+    fn passing_more_waypoints_on_a_longer_branch_does_not_count_as_further() {
+        let map = forked_map();
+        // Past two waypoints of the winding lane, but with 12 left to walk
+        let winding = walking_to(4, 3).progress(Vec2::new(6.0, 8.0), &map);
+        // Past one waypoint of the straight lane, with 6 left
+        let straight = walking_to(2, 2).progress(Vec2::new(4.0, 0.0), &map);
+        assert!(straight > winding);
+    }
+
+    #[test]
+    fn closer_to_the_next_waypoint_is_further_along() {
+        let map = forked_map();
+        let behind = walking_to(2, 2).progress(Vec2::new(3.0, 0.0), &map);
+        let ahead = walking_to(2, 2).progress(Vec2::new(5.0, 0.0), &map);
+        assert!(ahead > behind);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pathfinding_ranks_by_the_flow_field() {
+        let map = row_map();
+        let detoured = Target { mode: MovementMode::Pathfinding, cell: UVec2::new(1, 0), path_index: 10, ..Default::default() };
+        let near = Target { mode: MovementMode::Pathfinding, cell: UVec2::new(4, 0), path_index: 2, ..Default::default() };
+        assert_eq!(detoured.distance_left(Vec2::new(3.0, 1.0), &map), 8.0);
+        assert_eq!(near.distance_left(Vec2::new(8.0, 1.0), &map), 3.0);
+        assert!(near.progress(Vec2::new(8.0, 1.0), &map) > detoured.progress(Vec2::new(3.0, 1.0), &map));
+    }
+
+    #[test]
+    fn targets_that_can_not_finish_are_last() {
+        let map = row_map();
+        let lost = Target { mode: MovementMode::Pathfinding, cell: UVec2::new(9, 9), ..Default::default() };
+        assert_eq!(lost.progress(Vec2::ZERO, &map), f32::NEG_INFINITY);
+        assert_eq!(walking_to(42, 0).progress(Vec2::ZERO, &forked_map()), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn reloading_into_a_shorter_path_graph_heads_for_the_closest_node_left() {
+        let mut target = walking_to(5, 3);
+        let mut paths = PathGraph {
+            nodes: vec![PathNode::new(Vec2::new(0.0, 0.0)), PathNode::finish(Vec2::new(4.0, 0.0))],
+            spawns: vec![0],
+        };
+        paths.connect(0, 1);
+        paths.compute_remaining();
+        let shorter = GameMap { paths, ..Default::default() };
+
+        target.repoint(Vec2::new(9.0, 0.0), &shorter);
+        assert_eq!(target.node, 1);
+        assert_eq!(target.distance_left(Vec2::new(9.0, 0.0), &shorter), 5.0);
+    }
+
+    #[test]
+    fn reloading_with_another_movement_mode_switches_over() {
+        let mut target = walking_to(4, 3);
+        target.repoint(Vec2::new(7.0, 1.0), &row_map());
+        assert_eq!(target.mode, MovementMode::Pathfinding);
+        assert_eq!(target.cell, UVec2::new(3, 0));
+
+        target.repoint(Vec2::new(3.0, 0.0), &forked_map());
+        assert_eq!(target.mode, MovementMode::Waypoints);
+        assert_eq!(target.node, 1);
+    }
+
+    #[test]
+    fn reloading_into_an_empty_path_graph_leaves_nothing_to_index() {
+        let mut target = walking_to(4, 3);
+        target.repoint(Vec2::new(3.0, 0.0), &GameMap::default());
+        assert_eq!(target.node, 0);
+        assert_eq!(target.distance_left(Vec2::new(3.0, 0.0), &GameMap::default()), f32::INFINITY);
+    }
+}
@@ -1,86 +1,251 @@
+use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
 use bevy::time::Timer;
 use bevy::utils::FloatOrd;
-use bevy_inspector_egui::{Inspectable, RegisterInspectable};
 use crate::bullet::{Bullet, Lifetime};
+use crate::damage::Damage;
 use crate::game_assets::GameAssets;
+use bevy_mod_picking::PickableBundle;
+use crate::gameplay::{GameMap, LevelEntity, TOWER_BASE_HEIGHT, TowerBaseAssets};
+use crate::helpers::{intercept_on_route, ProjectileKind, TowerDef, TowerUpgradeDef, tower_defs_from_file};
 use crate::physics::PhysicsBundle;
 use crate::states::GameState;
-use crate::target::{Target};
+use crate::status_effects::{StatusEffect, StatusEffects, StatusOnHit};
+use crate::target::{Health, Target};
 use crate::weapons::{Laser, LineMaterial};
 
-#[derive(Component, Reflect, Default)]
+pub const TOWER_CATALOGUE_FILE: &str = "./assets/data/towers.json";
+
+/// Keeps a tower with a zero fire rate from dividing by zero, it fires once every 100 seconds instead
+const MIN_FIRE_RATE: f32 = 0.01;
+
+/// Sent after a tower has been paid for and placed
+pub struct TowerBuilt {
+    pub tower: Entity,
+    pub tower_type: TowerType,
+    pub cost: u32,
+    pub position: Vec3,
+}
+
+/// Sent after an upgrade has been paid for and applied to a tower
+pub struct TowerUpgraded {
+    pub tower: Entity,
+    /// Name of the upgrade that was bought
+    pub upgrade: String,
+    pub cost: u32,
+}
+
+/// Sent when a tower is sold, `refund` is paid back to the player
+pub struct TowerSold {
+    pub tower: Entity,
+    pub tower_type: TowerType,
+    pub refund: u32,
+    pub position: Vec3,
+}
+
+/// Stats of the shots a tower fires
+#[derive(Reflect, FromReflect, Clone, Debug, Default)]
+pub struct Projectile {
+    pub model: Handle<Scene>,
+    pub speed: f32,
+    /// Seconds before a shot that hit nothing disappears
+    pub lifetime: f32,
+    /// Size of the hit box
+    pub size: f32,
+}
+
+#[derive(Component, Reflect, Clone, Debug, Default)]
 #[reflect(Component)]
 pub struct Tower {
     pub shooting_timer: Timer,
     pub bullet_offset: Vec3,
     pub range: f32,
+    pub projectile: Projectile,
+    pub damage: Damage,
+    /// Left on whatever a shot hits
+    pub effect: Option<StatusEffect>,
+    /// Aim where the target will be when the shot arrives instead of where it is
+    pub lead_targets: bool,
 }
 
-#[derive(Inspectable, Component, Clone, Copy, Debug)]
-pub enum TowerType {
-    Lazer,
-    Cannon,
-    Rock,
+/// Which target in range a tower shoots at
+#[derive(Component, Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[reflect(Component)]
+pub enum TargetingMode {
+    /// Furthest along its path
+    #[default]
+    First,
+    /// Least far along its path
+    Last,
+    /// Most health left
+    Strongest,
+    /// Least health left
+    Weakest,
+    Closest,
+    /// Highest speed, slows and freezes included
+    Fastest,
 }
 
-impl TowerType {
-    fn get_tower(&self, assets: &GameAssets) -> (Handle<Scene>, Tower) {
+impl TargetingMode {
+    pub const ALL: [TargetingMode; 6] = [
+        TargetingMode::First,
+        TargetingMode::Last,
+        TargetingMode::Strongest,
+        TargetingMode::Weakest,
+        TargetingMode::Closest,
+        TargetingMode::Fastest,
+    ];
+
+    /// How much the tower wants to shoot at a target, the highest score gets shot
+    fn score(&self, progress: f32, health: f32, distance: f32, speed: f32) -> f32 {
         match self {
-            TowerType::Lazer => (
-                assets.tower.clone(),
-                Tower {
-                    shooting_timer: Timer::from_seconds(0.25, TimerMode::Repeating),
-                    bullet_offset: Vec3::ZERO,
-                    range: 4.5,
-                }
-            ),
-            TowerType::Cannon => (
-                assets.tower.clone(),
-                Tower {
-                    shooting_timer: Timer::from_seconds(0.5, TimerMode::Repeating),
-                    bullet_offset: Vec3::ZERO,
-                    range: 4.5,
-                }
-            ),
-            TowerType::Rock => (
-                assets.tower.clone(),
-                Tower {
-                    shooting_timer: Timer::from_seconds(0.75, TimerMode::Repeating),
-                    bullet_offset: Vec3::ZERO,
-                    range: 4.5,
-                }
-            )
+            TargetingMode::First => progress,
+            TargetingMode::Last => -progress,
+            TargetingMode::Strongest => health,
+            TargetingMode::Weakest => -health,
+            TargetingMode::Closest => -distance,
+            TargetingMode::Fastest => speed,
         }
     }
+}
 
-    fn get_bullet(&self, direction: Vec3, assets: &GameAssets) -> (Handle<Scene>, Bullet) {
-        match self {
-            TowerType::Lazer => (
-                assets.bullet.clone(),
-                Bullet {
-                    direction,
-                    speed: 10.5,
-                }
-            ),
-            TowerType::Cannon => (
-                assets.bullet.clone(),
-                Bullet {
-                    direction,
-                    speed: 6.5,
-                }
-            ),
-            TowerType::Rock => (
-                assets.bullet.clone(),
-                Bullet {
-                    direction,
-                    speed: 3.5,
+/// Upgrades bought for a tower, as a path through the upgrade tree of its TowerKind
+#[derive(Component, Reflect, Clone, Debug, Default)]
+#[reflect(Component)]
+pub struct TowerTier {
+    /// Which of the offered upgrades was picked at every tier
+    pub path: Vec<usize>,
+    /// Funds spent on the tower, building it included
+    pub spent: u32,
+}
+
+impl TowerTier {
+    /// A freshly built tower is tier 1
+    pub fn tier(&self) -> usize {
+        self.path.len() + 1
+    }
+}
+
+/// The scene of a tower, swapped out when an upgrade brings a new model
+#[derive(Component)]
+pub struct TowerModel;
+
+/// One tier of a tower with its assets loaded, see [TowerUpgradeDef]
+#[derive(Debug, Clone)]
+pub struct TowerUpgrade {
+    pub name: String,
+    pub cost: u32,
+    pub model: Option<Handle<Scene>>,
+    pub fire_rate: Option<f32>,
+    pub range: Option<f32>,
+    pub damage: Option<Damage>,
+    /// Upgrades offered once this one has been bought
+    pub upgrades: Vec<TowerUpgrade>,
+}
+
+impl TowerUpgrade {
+    fn load(def: TowerUpgradeDef, asset_server: &AssetServer) -> Self {
+        TowerUpgrade {
+            name: def.name,
+            cost: def.cost,
+            model: def.model.map(|model| asset_server.load(model.as_str())),
+            fire_rate: def.fire_rate,
+            range: def.range,
+            damage: def.damage,
+            upgrades: def.upgrades.into_iter().map(|u| TowerUpgrade::load(u, asset_server)).collect(),
+        }
+    }
+
+    /// Changes the stats of a tower in place, the model is up to the caller
+    pub fn apply(&self, tower: &mut Tower) {
+        if let Some(fire_rate) = self.fire_rate {
+            tower.shooting_timer = shooting_timer(fire_rate);
+        }
+        if let Some(range) = self.range {
+            tower.range = range;
+        }
+        if let Some(damage) = self.damage {
+            tower.damage = damage;
+        }
+    }
+}
+
+/// Name of the catalogue entry a tower, or a build button, is for
+#[derive(Component, Reflect, Clone, Debug, Default, PartialEq, Eq)]
+#[reflect(Component)]
+pub struct TowerType(pub String);
+
+/// A tower that can be built, as read from the tower catalogue file
+#[derive(Debug, Clone)]
+pub struct TowerKind {
+    pub name: String,
+    pub cost: u32,
+    pub icon: Handle<Image>,
+    pub model: Handle<Scene>,
+    pub projectile_kind: ProjectileKind,
+    /// Stats of a freshly built tower
+    pub tower: Tower,
+    pub upgrades: Vec<TowerUpgrade>,
+    /// Share of the build and upgrade costs paid back when sold
+    pub refund_share: f32,
+}
+
+impl TowerKind {
+    /// Upgrades on offer for a tower of this kind, more than one means picking a specialisation
+    pub fn next_upgrades(&self, tier: &TowerTier) -> &[TowerUpgrade] {
+        let mut offered = self.upgrades.as_slice();
+        for &choice in &tier.path {
+            match offered.get(choice) {
+                Some(upgrade) => offered = &upgrade.upgrades,
+                None => return &[],
+            }
+        }
+        offered
+    }
+
+    /// What selling a tower of this kind pays back
+    pub fn refund(&self, tier: &TowerTier) -> u32 {
+        (tier.spent as f32 * self.refund_share.clamp(0.0, 1.0)).floor() as u32
+    }
+
+    /// Name of the last upgrade bought, or of the kind itself
+    pub fn tier_name(&self, tier: &TowerTier) -> &str {
+        let mut name = self.name.as_str();
+        let mut offered = self.upgrades.as_slice();
+        for &choice in &tier.path {
+            match offered.get(choice) {
+                Some(upgrade) => {
+                    name = upgrade.name.as_str();
+                    offered = &upgrade.upgrades;
                 }
-            )
+                None => break,
+            }
         }
+        name
     }
 }
 
+/// Every tower the player can build, in build menu order
+#[derive(Resource, Default)]
+pub struct TowerCatalogue {
+    kinds: Vec<TowerKind>,
+}
+
+impl TowerCatalogue {
+    pub fn get(&self, name: &str) -> Option<&TowerKind> {
+        self.kinds.iter().find(|k| k.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TowerKind> {
+        self.kinds.iter()
+    }
+}
+
+/// Timer that runs out `fire_rate` times a second
+pub fn shooting_timer(fire_rate: f32) -> Timer {
+    Timer::from_seconds(1.0 / fire_rate.max(MIN_FIRE_RATE), TimerMode::Repeating)
+}
+
 pub struct TowerPlugin;
 
 impl Plugin for TowerPlugin {
@@ -88,7 +253,14 @@ impl Plugin for TowerPlugin {
         app
             .add_plugin(MaterialPlugin::<LineMaterial>::default())
             .register_type::<Tower>()
-            .register_inspectable::<TowerType>()
+            .register_type::<Projectile>()
+            .register_type::<TowerType>()
+            .register_type::<TargetingMode>()
+            .add_startup_system_to_stage(StartupStage::PreStartup, load_tower_catalogue)
+            .register_type::<TowerTier>()
+            .add_event::<TowerBuilt>()
+            .add_event::<TowerUpgraded>()
+            .add_event::<TowerSold>()
             .add_system_set(
                 SystemSet::on_update(GameState::Gameplay)
                     .with_system(tower_shooting)
@@ -100,41 +272,72 @@ impl Plugin for TowerPlugin {
 
 fn tower_shooting(
     mut commands: Commands,
-    mut towers: Query<(Entity, &mut Tower, &TowerType, &GlobalTransform)>,
-    targets: Query<&GlobalTransform, With<Target>>,
-    assets: Res<GameAssets>,
+    mut towers: Query<(Entity, &mut Tower, &TargetingMode, &GlobalTransform)>,
+    targets: Query<(&GlobalTransform, &Target, &Health, Option<&StatusEffects>)>,
+    map: Res<GameMap>,
     time: Res<Time>,
 ) {
-    for (tower_ent, mut tower, tower_type, transform) in &mut towers {
+    for (tower_ent, mut tower, mode, transform) in &mut towers {
         tower.shooting_timer.tick(time.delta());
         if tower.shooting_timer.just_finished() {
             let bullet_spawn = transform.translation() + tower.bullet_offset;
 
             let direction = targets
                 .iter()
-                .filter(|target_transform| {
-                    Vec3::distance(target_transform.translation(), bullet_spawn) < tower.range
+                .map(|(target_transform, target, health, status)| {
+                    let speed = target.speed * status.map_or(1.0, StatusEffects::speed_multiplier);
+                    (target_transform.translation(), target, health, speed)
                 })
-                .min_by_key(|target_transform| {
-                    FloatOrd(Vec3::distance(target_transform.translation(), bullet_spawn))
+                .filter(|(position, ..)| {
+                    Vec3::distance(*position, bullet_spawn) < tower.range
                 })
-                .map(|closest_target| closest_target.translation() - bullet_spawn);
+                .max_by_key(|(position, target, health, speed)| {
+                    FloatOrd(mode.score(
+                        target.progress(position.xz(), &map),
+                        health.value,
+                        Vec3::distance(*position, bullet_spawn),
+                        *speed,
+                    ))
+                })
+                .map(|(position, target, _, speed)| {
+                    if !tower.lead_targets {
+                        return position - bullet_spawn;
+                    }
+                    // Targets walk on flat ground between waypoints, keep them at their current height
+                    let route: Vec<Vec3> = target.route_ahead(&map)
+                        .into_iter()
+                        .map(|point| point.extend(position.y).xzy())
+                        .collect();
+                    match intercept_on_route(bullet_spawn, tower.projectile.speed, position, speed, &route) {
+                        Some((aim, flight_time)) if flight_time <= tower.projectile.lifetime => aim - bullet_spawn,
+                        // The shot would be gone before it got there, shoot at the target anyway
+                        _ => position - bullet_spawn,
+                    }
+                });
 
             if let Some(direction) = direction {
-                let (model, bullet) = tower_type.get_bullet(direction, &assets);
+                let projectile = &tower.projectile;
                 commands.entity(tower_ent)
                     .with_children(|commands| {
-                        commands.spawn(SceneBundle {
-                            scene: model,
+                        let mut bullet_commands = commands.spawn(SceneBundle {
+                            scene: projectile.model.clone(),
                             transform: Transform::from_translation(tower.bullet_offset),
                             ..default()
-                        })
+                        });
+                        bullet_commands
                             .insert(Lifetime {
-                                timer: Timer::from_seconds(0.5, TimerMode::Once) // Bullet lifetime
+                                timer: Timer::from_seconds(projectile.lifetime, TimerMode::Once)
+                            })
+                            .insert(Bullet {
+                                direction,
+                                speed: projectile.speed,
                             })
-                            .insert(bullet)
+                            .insert(tower.damage)
                             .insert(Name::new("Bullet"))
-                            .insert(PhysicsBundle::moving_entity(Vec3::new(0.2, 0.2, 0.2)));
+                            .insert(PhysicsBundle::moving_entity(Vec3::splat(projectile.size)));
+                        if let Some(effect) = tower.effect {
+                            bullet_commands.insert(StatusOnHit(effect));
+                        }
                     });
             }
         }
@@ -144,19 +347,27 @@ fn tower_shooting(
 pub fn spawn_tower(
     commands: &mut Commands,
     assets: &GameAssets,
-    position: Vec3,
-    tower_type: TowerType,
+    tower_base: &TowerBaseAssets,
+    map: &GameMap,
+    mut position: Vec3,
+    kind: &TowerKind,
 ) -> Entity {
-    let (ts, tower) = tower_type.get_tower(assets);
-    info!("Spawning {:?} tower", tower_type);
+    info!("Spawning {} tower", kind.name);
+    // Sit on top of whatever terrain is under the tower
+    position.y = map.height_at(position.xz()) + TOWER_BASE_HEIGHT;
 
     let t_id = commands
         .spawn(SpatialBundle::from_transform(
             Transform::from_translation(position)
         ))
-        .insert(Name::new(format!("{:?}_tower", tower_type)))
-        .insert(tower_type)
-        .insert(tower)
+        .insert((Name::new(format!("{}_tower", kind.name)), LevelEntity))
+        .insert(TowerType(kind.name.clone()))
+        .insert(kind.tower.clone())
+        .insert(TargetingMode::default())
+        .insert(TowerTier { path: Vec::new(), spent: kind.cost })
+        // Only there to be picked, without a material it is never drawn
+        .insert(tower_base.mesh.clone())
+        .insert(PickableBundle::default())
         .with_children(|commands| {
             commands.spawn(SceneBundle {
                 scene: assets.pedestal.clone(),
@@ -165,17 +376,171 @@ pub fn spawn_tower(
             })
                 .insert(Name::new("Pedestal"));
             commands.spawn(SceneBundle {
-                scene: ts,
+                scene: kind.model.clone(),
                 transform: Transform::from_xyz(0.0, -1., 0.0),
                 ..default()
             })
-                .insert(Name::new("Tower base"));
+                .insert((Name::new("Tower base"), TowerModel));
         }).id();
-    match tower_type {
-        TowerType::Lazer => {
-            commands.entity(t_id).insert(Laser::default());
-        }
-        _ => {}
+    if kind.projectile_kind == ProjectileKind::Laser {
+        commands.entity(t_id).insert(Laser::default());
     }
     t_id
-}
\ No newline at end of file
+}
+
+fn load_tower_catalogue(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+) {
+    let mut defs = tower_defs_from_file(TOWER_CATALOGUE_FILE).unwrap_or_else(|e| {
+        error!("Failed to load the tower catalogue! {}", e);
+        Vec::new()
+    });
+    if defs.is_empty() {
+        warn!("{} has no towers, using the built in one", TOWER_CATALOGUE_FILE);
+        defs.push(TowerDef::default());
+    }
+
+    let mut kinds: Vec<TowerKind> = Vec::with_capacity(defs.len());
+    for def in defs {
+        if kinds.iter().any(|k| k.name == def.name) {
+            warn!("{} has more than one {} tower, only the first one can be built", TOWER_CATALOGUE_FILE, def.name);
+            continue;
+        }
+        kinds.push(TowerKind {
+            name: def.name,
+            cost: def.cost,
+            icon: asset_server.load(def.icon.as_str()),
+            model: asset_server.load(def.model.as_str()),
+            projectile_kind: def.projectile.kind,
+            tower: Tower {
+                shooting_timer: shooting_timer(def.fire_rate),
+                bullet_offset: Vec3::from(def.bullet_offset),
+                range: def.range,
+                projectile: Projectile {
+                    model: asset_server.load(def.projectile.model.as_str()),
+                    speed: def.projectile.speed,
+                    lifetime: def.projectile.lifetime,
+                    size: def.projectile.size,
+                },
+                damage: def.damage,
+                effect: def.effect.map(|e| e.to_effect()),
+                lead_targets: def.lead_targets,
+            },
+            upgrades: def.upgrades.into_iter().map(|u| TowerUpgrade::load(u, &asset_server)).collect(),
+            refund_share: def.refund_share,
+        });
+    }
+    commands.insert_resource(TowerCatalogue { kinds });
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (progress, health, distance, speed) of the targets in range
+    const TARGETS: [(f32, f32, f32, f32); 3] = [
+        (-2.0, 5.0, 3.0, 1.0),
+        (-8.0, 9.0, 1.0, 0.5),
+        (-5.0, 1.0, 4.0, 2.5),
+    ];
+
+    /// Index of the target a tower in `mode` shoots at
+    fn pick(mode: TargetingMode) -> usize {
+        (0..TARGETS.len())
+            .max_by_key(|&i| {
+                let (progress, health, distance, speed) = TARGETS[i];
+                FloatOrd(mode.score(progress, health, distance, speed))
+            })
+            .unwrap()
+    }
+
+    fn upgrade(name: &str, cost: u32, upgrades: Vec<TowerUpgrade>) -> TowerUpgrade {
+        TowerUpgrade { name: name.to_string(), cost, model: None, fire_rate: None, range: None, damage: None, upgrades }
+    }
+
+    /// Tier 2 "Heavy" branches into "Mortar" and "Gatling" at tier 3
+    fn cannon() -> TowerKind {
+        TowerKind {
+            name: "Cannon".to_string(),
+            cost: 4,
+            icon: Handle::default(),
+            model: Handle::default(),
+            projectile_kind: ProjectileKind::Bullet,
+            tower: Tower::default(),
+            upgrades: vec![upgrade("Heavy", 6, vec![upgrade("Mortar", 10, vec![]), upgrade("Gatling", 10, vec![])])],
+            refund_share: 0.75,
+        }
+    }
+
+    fn tier(path: &[usize], spent: u32) -> TowerTier {
+        TowerTier { path: path.to_vec(), spent }
+    }
+
+    fn names(upgrades: &[TowerUpgrade]) -> Vec<&str> {
+        upgrades.iter().map(|u| u.name.as_str()).collect()
+    }
+
+    #[test]
+    fn upgrades_follow_the_path_through_the_tree() {
+        let kind = cannon();
+        assert_eq!(names(kind.next_upgrades(&tier(&[], 4))), vec!["Heavy"]);
+        assert_eq!(names(kind.next_upgrades(&tier(&[0], 10))), vec!["Mortar", "Gatling"]);
+        assert!(kind.next_upgrades(&tier(&[0, 1], 20)).is_empty());
+        // A path the tree does not have offers nothing
+        assert!(kind.next_upgrades(&tier(&[3], 10)).is_empty());
+    }
+
+    #[test]
+    fn tier_name_is_the_last_upgrade_bought() {
+        let kind = cannon();
+        assert_eq!(kind.tier_name(&tier(&[], 4)), "Cannon");
+        assert_eq!(kind.tier_name(&tier(&[0], 10)), "Heavy");
+        assert_eq!(kind.tier_name(&tier(&[0, 1], 20)), "Gatling");
+        assert_eq!(tier(&[0, 1], 20).tier(), 3);
+    }
+
+    #[test]
+    fn refund_is_a_share_of_everything_spent() {
+        let mut kind = cannon();
+        assert_eq!(kind.refund(&tier(&[], 4)), 3);
+        assert_eq!(kind.refund(&tier(&[0, 0], 20)), 15);
+        // Rounded down, and never more than was spent
+        assert_eq!(kind.refund(&tier(&[0], 10)), 7);
+        kind.refund_share = 1.5;
+        assert_eq!(kind.refund(&tier(&[0], 10)), 10);
+        kind.refund_share = -1.0;
+        assert_eq!(kind.refund(&tier(&[0], 10)), 0);
+    }
+
+    #[test]
+    fn upgrades_only_change_what_they_set() {
+        let mut tower = Tower { range: 4.0, shooting_timer: shooting_timer(1.0), ..Default::default() };
+        let mut longer = upgrade("Long", 1, vec![]);
+        longer.range = Some(6.0);
+        longer.apply(&mut tower);
+        assert_eq!(tower.range, 6.0);
+        assert_eq!(tower.shooting_timer.duration(), shooting_timer(1.0).duration());
+
+        let mut faster = upgrade("Fast", 1, vec![]);
+        faster.fire_rate = Some(4.0);
+        faster.apply(&mut tower);
+        assert_eq!(tower.shooting_timer.duration().as_secs_f32(), 0.25);
+        assert_eq!(tower.range, 6.0);
+    }
+
+    #[test]
+    fn every_mode_picks_its_target() {
+        assert_eq!(pick(TargetingMode::First), 0);
+        assert_eq!(pick(TargetingMode::Last), 1);
+        assert_eq!(pick(TargetingMode::Strongest), 1);
+        assert_eq!(pick(TargetingMode::Weakest), 2);
+        assert_eq!(pick(TargetingMode::Closest), 1);
+        assert_eq!(pick(TargetingMode::Fastest), 2);
+    }
+
+    #[test]
+    fn targets_that_can_not_finish_are_shot_last_when_going_first() {
+        let stuck = TargetingMode::First.score(f32::NEG_INFINITY, 1.0, 1.0, 1.0);
+        assert!(stuck < TargetingMode::First.score(-100.0, 1.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,396 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy_mod_picking::Selection;
+use crate::game_assets::GameAssets;
+use crate::gameplay::{GameMap, GroundChangedEvent, LevelEntity, spawn_tower_base, TowerBaseAssets};
+use crate::player::Player;
+use crate::states::GameState;
+use crate::tower::{TargetingMode, Tower, TowerCatalogue, TowerModel, TowerSold, TowerTier, TowerType, TowerUpgraded};
+
+const BUTTON_COLOR: Color = Color::GRAY;
+const ACTIVE_BUTTON_COLOR: Color = Color::GOLD;
+const UNAFFORDABLE_BUTTON_COLOR: Color = Color::DARK_GRAY;
+
+/// Side panel with the settings of the selected tower
+pub struct TowerPanelPlugin;
+
+impl Plugin for TowerPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system_set(
+                SystemSet::on_pause(GameState::Gameplay)
+                    .with_system(despawn_tower_panel)
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Gameplay)
+                    .with_system(show_tower_panel)
+                    .with_system(targeting_button_clicked)
+                    .with_system(update_targeting_buttons.after(targeting_button_clicked))
+                    .with_system(lead_button_clicked)
+                    .with_system(update_lead_button.after(lead_button_clicked))
+                    .with_system(upgrade_button_clicked)
+                    .with_system(update_upgrade_buttons)
+                    .with_system(sell_button_clicked)
+            )
+        ;
+    }
+}
+
+#[derive(Component)]
+pub struct TowerPanelRoot {
+    /// Tower the panel is showing
+    pub tower: Entity,
+    /// Tier of the tower when the panel was made, the upgrades on offer change with it
+    pub tier: usize,
+}
+
+#[derive(Component)]
+pub struct TargetingModeButton(pub TargetingMode);
+
+/// Switches leading shots on and off
+#[derive(Component)]
+pub struct LeadTargetsButton;
+
+#[derive(Component)]
+pub struct SellButton;
+
+#[derive(Component)]
+pub struct UpgradeButton {
+    /// Index into the upgrades on offer
+    pub choice: usize,
+    pub cost: u32,
+}
+
+/// A small button with a label for the tower panel
+pub fn spawn_panel_button(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    text: &str,
+) -> Entity {
+    commands
+        .spawn(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Px(36.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                margin: UiRect::vertical(Val::Px(3.0)),
+                ..default()
+            },
+            background_color: BUTTON_COLOR.into(),
+            ..default()
+        })
+        .with_children(|commands| {
+            commands.spawn(TextBundle::from_section(
+                text,
+                TextStyle {
+                    font: assets.game_font.clone(),
+                    font_size: 20.0,
+                    color: Color::BLACK,
+                },
+            ));
+        }).id()
+}
+
+fn panel_text(assets: &GameAssets, text: &str, font_size: f32) -> TextBundle {
+    TextBundle::from_section(
+        text,
+        TextStyle {
+            font: assets.game_font.clone(),
+            font_size,
+            color: Color::WHITE,
+        },
+    ).with_style(Style {
+        margin: UiRect::vertical(Val::Px(6.0)),
+        ..default()
+    })
+}
+
+fn spawn_tower_panel(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    catalogue: &TowerCatalogue,
+    tower: Entity,
+    tower_type: &TowerType,
+    tier: &TowerTier,
+) {
+    let targeting_buttons: Vec<Entity> = TargetingMode::ALL
+        .iter()
+        .map(|mode| {
+            let button = spawn_panel_button(commands, assets, &format!("{:?}", mode));
+            commands.entity(button).insert(TargetingModeButton(*mode)).id()
+        })
+        .collect();
+    let lead_button = spawn_panel_button(commands, assets, "Lead shots");
+    commands.entity(lead_button).insert(LeadTargetsButton);
+
+    let kind = catalogue.get(&tower_type.0);
+    let title = match kind {
+        Some(kind) => format!("{} (tier {})", kind.tier_name(tier), tier.tier()),
+        None => tower_type.0.clone(),
+    };
+    let upgrade_buttons: Vec<Entity> = kind
+        .map(|kind| kind.next_upgrades(tier))
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(choice, upgrade)| {
+            let button = spawn_panel_button(commands, assets, &format!("{} - {}", upgrade.name, upgrade.cost));
+            commands.entity(button).insert(UpgradeButton { choice, cost: upgrade.cost }).id()
+        })
+        .collect();
+    let upgrades_label = match upgrade_buttons.len() {
+        0 => "Fully upgraded",
+        1 => "Upgrade",
+        _ => "Specialise",
+    };
+
+    let refund = kind.map_or(0, |kind| kind.refund(tier));
+    let sell_button = spawn_panel_button(commands, assets, &format!("Sell + {}", refund));
+    commands.entity(sell_button).insert(SellButton);
+
+    commands.spawn(NodeBundle {
+        style: Style {
+            size: Size::new(Val::Px(220.0), Val::Auto),
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(10.0),
+                top: Val::Percent(15.0),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(10.0)),
+            ..default()
+        },
+        background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+        ..default()
+    })
+        .insert((Name::new("Tower_panel"), TowerPanelRoot { tower, tier: tier.tier() }, LevelEntity))
+        .with_children(|commands| {
+            commands.spawn(panel_text(assets, &title, 28.0));
+            commands.spawn(panel_text(assets, "Targeting", 22.0));
+        })
+        .push_children(&targeting_buttons)
+        .add_child(lead_button)
+        .with_children(|commands| {
+            commands.spawn(panel_text(assets, upgrades_label, 22.0));
+        })
+        .push_children(&upgrade_buttons)
+        .with_children(|commands| {
+            commands.spawn(panel_text(assets, "", 12.0));
+        })
+        .add_child(sell_button);
+}
+
+/// Shows the panel of the selected tower, and only while a tower is selected
+fn show_tower_panel(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    catalogue: Res<TowerCatalogue>,
+    towers: Query<(Entity, &Selection, &TowerType, &TowerTier), With<Tower>>,
+    panels: Query<(Entity, &TowerPanelRoot)>,
+) {
+    let selected = towers.iter().find(|(_, selection, ..)| selection.selected());
+    if let (Some((tower, _, _, tier)), Ok((_, panel))) = (selected, panels.get_single()) {
+        if panel.tower == tower && panel.tier == tier.tier() {
+            return;
+        }
+    }
+
+    for (panel, _) in &panels {
+        commands.entity(panel).despawn_recursive();
+    }
+    if let Some((tower, _, tower_type, tier)) = selected {
+        spawn_tower_panel(&mut commands, &assets, &catalogue, tower, tower_type, tier);
+    }
+}
+
+fn despawn_tower_panel(
+    mut commands: Commands,
+    panels: Query<Entity, With<TowerPanelRoot>>,
+) {
+    for panel in &panels {
+        commands.entity(panel).despawn_recursive();
+    }
+}
+
+fn targeting_button_clicked(
+    interactions: Query<(&Interaction, &TargetingModeButton), Changed<Interaction>>,
+    panels: Query<&TowerPanelRoot>,
+    mut towers: Query<&mut TargetingMode>,
+) {
+    for (interaction, button) in &interactions {
+        if !matches!(interaction, Interaction::Clicked) {
+            continue;
+        }
+        for panel in &panels {
+            if let Ok(mut mode) = towers.get_mut(panel.tower) {
+                info!("Tower now targets {:?}", button.0);
+                *mode = button.0;
+            }
+        }
+    }
+}
+
+fn update_targeting_buttons(
+    mut buttons: Query<(&TargetingModeButton, &mut BackgroundColor)>,
+    panels: Query<&TowerPanelRoot>,
+    towers: Query<&TargetingMode>,
+) {
+    let mode = match panels.get_single().ok().and_then(|panel| towers.get(panel.tower).ok()) {
+        Some(mode) => *mode,
+        None => return,
+    };
+    for (button, mut color) in &mut buttons {
+        *color = match button.0 == mode {
+            true => ACTIVE_BUTTON_COLOR.into(),
+            false => BUTTON_COLOR.into(),
+        };
+    }
+}
+
+fn lead_button_clicked(
+    interactions: Query<&Interaction, (With<LeadTargetsButton>, Changed<Interaction>)>,
+    panels: Query<&TowerPanelRoot>,
+    mut towers: Query<&mut Tower>,
+) {
+    for interaction in &interactions {
+        if !matches!(interaction, Interaction::Clicked) {
+            continue;
+        }
+        for panel in &panels {
+            if let Ok(mut tower) = towers.get_mut(panel.tower) {
+                tower.lead_targets = !tower.lead_targets;
+                info!("Tower leads its shots: {}", tower.lead_targets);
+            }
+        }
+    }
+}
+
+fn update_lead_button(
+    mut buttons: Query<&mut BackgroundColor, With<LeadTargetsButton>>,
+    panels: Query<&TowerPanelRoot>,
+    towers: Query<&Tower>,
+) {
+    let lead_targets = match panels.get_single().ok().and_then(|panel| towers.get(panel.tower).ok()) {
+        Some(tower) => tower.lead_targets,
+        None => return,
+    };
+    for mut color in &mut buttons {
+        *color = match lead_targets {
+            true => ACTIVE_BUTTON_COLOR.into(),
+            false => BUTTON_COLOR.into(),
+        };
+    }
+}
+
+fn upgrade_button_clicked(
+    interactions: Query<(&Interaction, &UpgradeButton), Changed<Interaction>>,
+    panels: Query<&TowerPanelRoot>,
+    mut towers: Query<(&TowerType, &mut Tower, &mut TowerTier, &Children)>,
+    mut models: Query<&mut Handle<Scene>, With<TowerModel>>,
+    mut player: Query<&mut Player>,
+    catalogue: Res<TowerCatalogue>,
+    mut tower_upgraded: EventWriter<TowerUpgraded>,
+) {
+    for (interaction, button) in &interactions {
+        if !matches!(interaction, Interaction::Clicked) {
+            continue;
+        }
+        let panel = match panels.get_single() {
+            Ok(panel) => panel,
+            Err(_) => continue,
+        };
+        let (tower_type, mut tower, mut tier, children) = match towers.get_mut(panel.tower) {
+            Ok(tower) => tower,
+            Err(_) => continue,
+        };
+        let upgrade = match catalogue.get(&tower_type.0).and_then(|kind| kind.next_upgrades(&tier).get(button.choice)) {
+            Some(upgrade) => upgrade,
+            None => {
+                warn!("{} tower has no upgrade {} at tier {}", tower_type.0, button.choice, tier.tier());
+                continue;
+            }
+        };
+
+        let mut player = player.single_mut();
+        if player.spend_funds(upgrade.cost).is_none() {
+            info!("Cannot afford {}, it costs {} but only have {}", upgrade.name, upgrade.cost, player.get_funds());
+            continue;
+        }
+
+        info!("Upgrading {} tower to {}", tower_type.0, upgrade.name);
+        upgrade.apply(&mut tower);
+        if let Some(model) = &upgrade.model {
+            // The scene spawner swaps the scene when the handle changes
+            let mut models = models.iter_many_mut(children);
+            while let Some(mut scene) = models.fetch_next() {
+                *scene = model.clone();
+            }
+        }
+        tier.path.push(button.choice);
+        tier.spent += upgrade.cost;
+        tower_upgraded.send(TowerUpgraded {
+            tower: panel.tower,
+            upgrade: upgrade.name.clone(),
+            cost: upgrade.cost,
+        });
+    }
+}
+
+fn update_upgrade_buttons(
+    mut buttons: Query<(&UpgradeButton, &mut BackgroundColor)>,
+    player: Query<&Player>,
+) {
+    let player = player.single();
+    for (button, mut color) in &mut buttons {
+        *color = match player.get_funds() >= button.cost {
+            true => BUTTON_COLOR.into(),
+            false => UNAFFORDABLE_BUTTON_COLOR.into(),
+        };
+    }
+}
+
+/// Sells the tower of the panel and puts an empty tower base back in its place
+#[allow(clippy::too_many_arguments)]
+fn sell_button_clicked(
+    mut commands: Commands,
+    interactions: Query<&Interaction, (With<SellButton>, Changed<Interaction>)>,
+    panels: Query<&TowerPanelRoot>,
+    towers: Query<(&TowerType, &TowerTier, &Transform)>,
+    catalogue: Res<TowerCatalogue>,
+    tower_base: Res<TowerBaseAssets>,
+    mut map: ResMut<GameMap>,
+    mut tower_sold: EventWriter<TowerSold>,
+    mut ground_changed: EventWriter<GroundChangedEvent>,
+) {
+    for interaction in &interactions {
+        if !matches!(interaction, Interaction::Clicked) {
+            continue;
+        }
+        for panel in &panels {
+            let (tower_type, tier, transform) = match towers.get(panel.tower) {
+                Ok(tower) => tower,
+                Err(_) => continue,
+            };
+            let refund = catalogue.get(&tower_type.0).map_or(0, |kind| kind.refund(tier));
+            let position = transform.translation;
+            info!("Selling {} tower for {}", tower_type.0, refund);
+
+            commands.entity(panel.tower).despawn_recursive();
+            // When mazing the ground is picked again instead of a base
+            if !map.mazing {
+                spawn_tower_base(&mut commands, &tower_base, position);
+            }
+            // A cell the tower blocked while mazing opens up for enemies again
+            if let Some(cell) = map.world_to_cell(position.xz()) {
+                map.unblock_cell(cell, &mut ground_changed);
+            }
+            tower_sold.send(TowerSold {
+                tower: panel.tower,
+                tower_type: tower_type.clone(),
+                refund,
+                position,
+            });
+        }
+    }
+}
@@ -1,10 +1,15 @@
 use bevy::ecs::query::QuerySingleError;
+use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
-use bevy_mod_picking::{PickingEvent, Selection};
+use bevy_mod_picking::{PickingCamera, PickingEvent, Selection};
+use crate::camera::MainGameCamera;
 use crate::game_assets::GameAssets;
+use crate::gameplay::{GameMap, GroundChangedEvent, GroundCollider, LevelEntity, PickedCellMarker, TOWER_BASE_HEIGHT, TowerBase, TowerBaseAssets};
 use crate::player::Player;
 use crate::states::GameState;
-use crate::tower::{spawn_tower, TowerType};
+use crate::target::{EnemyKilled, Target};
+use crate::tower::{spawn_tower, TowerBuilt, TowerCatalogue, TowerType};
+use crate::waves::WaveState;
 
 #[derive(Component)]
 pub struct TowerUiRoot;
@@ -18,6 +23,19 @@ pub struct MoneyUiElement;
 #[derive(Component)]
 pub struct LivesUiElement;
 
+#[derive(Component)]
+pub struct WaveUiElement;
+
+/// Seconds a bounty stays on screen
+const BOUNTY_POPUP_TIME: f32 = 1.0;
+
+/// Floating "+N" over the spot an enemy was killed
+#[derive(Component)]
+pub struct BountyPopup {
+    world_position: Vec3,
+    timer: Timer,
+}
+
 #[derive(Component, Reflect, Default)]
 #[reflect(Component)]
 pub struct TowerButtonState {
@@ -44,13 +62,17 @@ impl Plugin for GameUiPlugin {
             )
             .add_system_set(
                 SystemSet::on_update(GameState::Gameplay)
-                    .with_system(create_ui_on_selection)
+                    .with_system(pick_ground_cell)
+                    .with_system(create_ui_on_selection.after(pick_ground_cell))
                     .with_system(interaction_test)
                     .with_system(tower_button_clicked)
                     .with_system(process_keyboard_input)
                     .with_system(update_tower_button_states)
                     .with_system(update_tower_button_states.after(create_ui_on_selection)) // Make sure we update the state after the UI has been created
                     .with_system(update_player_ui)
+                    .with_system(update_wave_ui)
+                    .with_system(spawn_bounty_popups)
+                    .with_system(update_bounty_popups)
 
                 // Testing the picking mod
             )
@@ -92,7 +114,7 @@ fn spawn_gameplay_ui(
         },
         ..default()
     })
-        .insert((Name::new("Game_Ui_Root"), GameplayUiRoot))
+        .insert((Name::new("Game_Ui_Root"), GameplayUiRoot, LevelEntity))
         .with_children(|commands| {
             commands
                 .spawn(NodeBundle { // This is the row where text components for lives and money will live
@@ -139,6 +161,23 @@ fn spawn_gameplay_ui(
                         ),
                         ..default()
                     }).insert(LivesUiElement);
+
+                commands
+                    .spawn(TextBundle {
+                        style: Style {
+                            margin: UiRect::all(Val::Percent(1.2)),
+                            ..default()
+                        },
+                        text: Text::from_section(
+                            "Wave X of Y",
+                            TextStyle {
+                                font: assets.game_font.clone(),
+                                font_size: 28.0,
+                                color: Color::WHITE,
+                            },
+                        ),
+                        ..default()
+                    }).insert(WaveUiElement);
             });
         })
     ;
@@ -146,13 +185,56 @@ fn spawn_gameplay_ui(
 
 fn interaction_test() {}
 
+/// When mazing, works out which cell of the ground was clicked and marks it
+fn pick_ground_cell(
+    mut events: EventReader<PickingEvent>,
+    cameras: Query<&PickingCamera>,
+    mut grounds: Query<(Entity, &mut GroundCollider, &Selection)>,
+    mut markers: Query<(&mut Transform, &mut Visibility), With<PickedCellMarker>>,
+    map: Res<GameMap>,
+) {
+    for event in events.iter() {
+        let clicked = match event {
+            PickingEvent::Clicked(entity) => *entity,
+            _ => continue,
+        };
+        let hit = cameras
+            .iter()
+            .filter_map(|camera| camera.get_nearest_intersection())
+            .find(|(entity, _)| *entity == clicked)
+            .map(|(_, hit)| hit.position());
+        for (entity, mut ground, _) in &mut grounds {
+            if entity == clicked {
+                ground.picked = hit
+                    .and_then(|position| map.world_to_cell(position.xz()))
+                    .filter(|cell| map.is_tower_slot(*cell) && !map.blocked.contains_key(cell));
+            }
+        }
+    }
+
+    for (_, mut ground, selection) in &mut grounds {
+        if !selection.selected() {
+            ground.picked = None;
+        }
+        for (mut transform, mut visibility) in &mut markers {
+            visibility.is_visible = ground.picked.is_some();
+            if let Some(cell) = ground.picked {
+                // Just above the ground so it does not flicker
+                transform.translation = map.cell_center(cell).extend(map.cell_height(cell) + 0.01).xzy();
+            }
+        }
+    }
+}
+
 fn create_ui_on_selection(
     mut commands: Commands,
-    assets: Res<AssetServer>,
-    selections: Query<&Selection>, //bevy selection crate
+    catalogue: Res<TowerCatalogue>,
+    selections: Query<&Selection, With<TowerBase>>, //bevy selection crate
+    grounds: Query<(&GroundCollider, &Selection)>,
     root: Query<Entity, With<TowerUiRoot>>, // we need to get our ui root so we can (de)spawn it
 ) {
-    let at_least_one_selected = selections.iter().any(|s| s.selected());
+    let at_least_one_selected = selections.iter().any(|s| s.selected())
+        || grounds.iter().any(|(ground, s)| s.selected() && ground.picked.is_some());
     match root.get_single() {
         Ok(root) => {
             if !at_least_one_selected {
@@ -163,7 +245,7 @@ fn create_ui_on_selection(
         Err(QuerySingleError::NoEntities(..)) => {
             // we have something selected but NO UI root (towers) present, spawn it
             if at_least_one_selected {
-                create_ui(&mut commands, &assets);
+                create_ui(&mut commands, &catalogue);
             }
         }
         _ => unreachable!("Too many ui tower roots"),
@@ -172,16 +254,8 @@ fn create_ui_on_selection(
 
 fn create_ui(
     commands: &mut Commands,
-    assets: &AssetServer,
+    catalogue: &TowerCatalogue,
 ) {
-    let tower_types = [TowerType::Lazer, TowerType::Cannon, TowerType::Rock];
-    let tower_costs = [1, 2, 5];
-
-    let tower_icons: [Handle<Image>; 3] = [
-        assets.load("images/rock_tower_icon.png"),
-        assets.load("images/rock_tower_icon.png"),
-        assets.load("images/rock_tower_icon.png"),
-    ];
     commands
         .spawn(NodeBundle {
             style: Style {
@@ -191,10 +265,10 @@ fn create_ui(
             },
             ..default()
         })
-        .insert(TowerUiRoot)
+        .insert((TowerUiRoot, LevelEntity))
         .insert(Name::new("UI_Root"))
         .with_children(|commands| {
-            for i in 0..tower_types.len() {
+            for kind in catalogue.iter() {
                 commands.spawn(ButtonBundle {
                     style: Style {
                         size: Size::new(Val::Percent(15.0 * 9.0 / 16.0), Val::Percent(15.0)),
@@ -202,46 +276,102 @@ fn create_ui(
                         margin: UiRect::all(Val::Px(5.0)),
                         ..default()
                     },
-                    image: tower_icons[i].clone().into(),
+                    image: kind.icon.clone().into(),
                     ..default()
                 })
                     .insert(TowerButtonState {
-                        cost: tower_costs[i],
+                        cost: kind.cost,
                         affordable: false,
                     })
-                    .insert(Name::new(format!("Tower_{:?}", tower_types[i])))
-                    .insert(tower_types[i]);
+                    .insert(Name::new(format!("Tower_{}", kind.name)))
+                    .insert(TowerType(kind.name.clone()));
             }
         });
 }
 
+#[allow(clippy::too_many_arguments)]
 fn tower_button_clicked(
     interactions: Query<(&Interaction, &TowerType, &TowerButtonState), Changed<Interaction>>, // Query will return ONLY changed interactions
     mut commands: Commands,
-    selections: Query<(Entity, &Selection, &Transform)>,
+    selections: Query<(Entity, &Selection, &Transform), With<TowerBase>>,
+    mut grounds: Query<(&mut GroundCollider, &mut Selection), Without<TowerBase>>,
     mut player: Query<&mut Player>,
     assets: Res<GameAssets>,
-    audio: Res<Audio>,
+    tower_base: Res<TowerBaseAssets>,
+    mut map: ResMut<GameMap>,
+    targets: Query<(&Target, &Transform)>,
+    mut tower_built: EventWriter<TowerBuilt>,
+    mut ground_changed: EventWriter<GroundChangedEvent>,
+    catalogue: Res<TowerCatalogue>,
 ) {
     let mut player = player.single_mut();
     for (interaction, tower_type, button_state) in &interactions {
         if matches!(interaction, Interaction::Clicked) {
-            for (entity, selection, transform) in &selections {
-                if selection.selected() {
-                    if player.get_funds() >= button_state.cost {
-                        match player.spend_funds(button_state.cost) {
-                            None => {
-                                warn!("Player balance overflow error");
+            let kind = match catalogue.get(&tower_type.0) {
+                Some(kind) => kind,
+                None => {
+                    warn!("No {} tower in the catalogue", tower_type.0);
+                    continue;
+                }
+            };
+            // Selected tower bases, and when mazing the cell picked on the ground
+            let mut spots: Vec<(Option<Entity>, Vec3)> = selections
+                .iter()
+                .filter(|(_, selection, _)| selection.selected())
+                .map(|(entity, _, transform)| (Some(entity), transform.translation))
+                .collect();
+            for (ground, selection) in &grounds {
+                if let (Some(cell), true) = (ground.picked, selection.selected()) {
+                    spots.push((None, map.cell_center(cell).extend(map.cell_height(cell) + TOWER_BASE_HEIGHT).xzy()));
+                }
+            }
+
+            for (base, position) in spots {
+                // When mazing the tower blocks its cell, but it may never wall off the Finish
+                let blocked_cell = match map.world_to_cell(position.xz()) {
+                    Some(cell) if map.mazing => Some(cell),
+                    _ => None,
+                };
+                if let Some(cell) = blocked_cell {
+                    let occupied: Vec<UVec2> = targets
+                        .iter()
+                        .flat_map(|(target, t)| [map.world_to_cell(t.translation.xz()), Some(target.cell)])
+                        .flatten()
+                        .collect();
+                    if player.get_funds() >= button_state.cost && !map.try_block_cell(cell, &occupied, &mut ground_changed) {
+                        info!("Cannot build {} tower there, it would block every path to the finish", kind.name);
+                        continue;
+                    }
+                }
+
+                if player.get_funds() >= button_state.cost {
+                    match player.spend_funds(button_state.cost) {
+                        None => {
+                            warn!("Player balance overflow error");
+                            if let Some(cell) = blocked_cell {
+                                map.unblock_cell(cell, &mut ground_changed);
                             }
-                            Some(_) => {
-                                audio.play(assets.tower_place_sound.clone());
-                                commands.entity(entity).despawn_recursive();
-                                spawn_tower(&mut commands, &assets, transform.translation, *tower_type);
+                        }
+                        Some(_) => {
+                            match base {
+                                Some(base) => commands.entity(base).despawn_recursive(),
+                                // The picked cell is taken now, the build menu goes away with the selection
+                                None => for (mut ground, mut selection) in &mut grounds {
+                                    ground.picked = None;
+                                    selection.set_selected(false);
+                                },
                             }
+                            let tower = spawn_tower(&mut commands, &assets, &tower_base, &map, position, kind);
+                            tower_built.send(TowerBuilt {
+                                tower,
+                                tower_type: tower_type.clone(),
+                                cost: button_state.cost,
+                                position,
+                            });
                         }
-                    } else {
-                        info!("Cannot afford {:?} tower, it costs {} but only have {}", tower_type,button_state.cost,player.get_funds());
                     }
+                } else {
+                    info!("Cannot afford {} tower, it costs {} but only have {}", kind.name, button_state.cost, player.get_funds());
                 }
             }
         }
@@ -285,6 +415,85 @@ fn update_player_ui(
     );
 }
 
+fn spawn_bounty_popups(
+    mut commands: Commands,
+    mut enemy_killed: EventReader<EnemyKilled>,
+    assets: Res<GameAssets>,
+) {
+    for event in enemy_killed.iter().filter(|e| e.bounty > 0) {
+        commands.spawn(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            text: Text::from_section(
+                format!("+{}", event.bounty),
+                TextStyle {
+                    font: assets.game_font.clone(),
+                    font_size: 24.0,
+                    color: Color::GOLD,
+                },
+            ),
+            // Hidden until it has been put over the enemy
+            visibility: Visibility::INVISIBLE,
+            ..default()
+        })
+            .insert(BountyPopup {
+                world_position: event.position,
+                timer: Timer::from_seconds(BOUNTY_POPUP_TIME, TimerMode::Once),
+            })
+            .insert((Name::new("Bounty_popup"), LevelEntity));
+    }
+}
+
+/// Rises and fades the bounties, keeping them over the spot of the kill when the camera moves
+fn update_bounty_popups(
+    mut commands: Commands,
+    mut popups: Query<(Entity, &mut BountyPopup, &mut Style, &mut Text, &mut Visibility)>,
+    camera: Query<(&Camera, &GlobalTransform), With<MainGameCamera>>,
+    time: Res<Time>,
+) {
+    let (camera, camera_transform) = match camera.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    for (entity, mut popup, mut style, mut text, mut visibility) in &mut popups {
+        popup.timer.tick(time.delta());
+        if popup.timer.finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+
+        let rise = Vec3::Y * popup.timer.percent() * 0.5;
+        match camera.world_to_viewport(camera_transform, popup.world_position + rise) {
+            Some(screen) => {
+                // Viewport coordinates start at the bottom left
+                style.position.left = Val::Px(screen.x);
+                style.position.bottom = Val::Px(screen.y);
+                visibility.is_visible = true;
+            }
+            None => visibility.is_visible = false,
+        }
+        text.sections[0].style.color.set_a(popup.timer.percent_left());
+    }
+}
+
+fn update_wave_ui(
+    waves: Res<WaveState>,
+    mut wave_ui: Query<&mut Text, With<WaveUiElement>>,
+) {
+    let mut wave_ui = wave_ui.single_mut();
+    let text = match waves.next_wave_in() {
+        Some(seconds) => format!("Wave {} of {}, next in {:.0}s", waves.current, waves.total, seconds.ceil()),
+        None => format!("Wave {} of {}", waves.current, waves.total),
+    };
+
+    *wave_ui = Text::from_section(
+        text,
+        wave_ui.sections[0].style.clone(),
+    );
+}
+
 fn process_keyboard_input(
     mut game_state: ResMut<State<GameState>>,
     mut keyboard: ResMut<Input<KeyCode>>,
@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use crate::gameplay::{GameMap, LevelCompleteEvent};
+use crate::helpers::{MIN_GROUP_SPACING, WaveGroup};
+use crate::states::GameState;
+use crate::target::{EnemyRegistry, spawn_target, Target};
+
+pub struct WavePlugin;
+
+impl Plugin for WavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<WaveState>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::Gameplay)
+                    .with_system(start_waves)
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Gameplay)
+                    .with_system(run_waves)
+            )
+        ;
+    }
+}
+
+/// Spawns the enemies of one group of the running wave
+struct GroupSpawner {
+    group: WaveGroup,
+    spawned: u32,
+    /// Runs out for every enemy, the first time after the delay of the group
+    timer: Timer,
+}
+
+impl GroupSpawner {
+    fn new(group: &WaveGroup, enemies: &EnemyRegistry) -> Self {
+        if enemies.get(&group.enemy).is_none() {
+            warn!("Unknown enemy kind {}, sending the default kind instead", group.enemy);
+        }
+        GroupSpawner {
+            group: group.clone(),
+            spawned: 0,
+            timer: Timer::from_seconds(group.delay.max(0.0), TimerMode::Once),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.spawned >= self.group.count
+    }
+}
+
+/// Wave `current` of `total`, `current` is 0 until the first wave starts
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct WaveState {
+    pub current: usize,
+    pub total: usize,
+    /// Counts down to the next wave, only runs once the previous wave has been cleared
+    pub countdown: Timer,
+    /// Set once the last wave has been cleared
+    pub victory: bool,
+    #[reflect(ignore)]
+    spawners: Vec<GroupSpawner>,
+}
+
+impl WaveState {
+    /// True while enemies of the current wave are still to come
+    pub fn is_spawning(&self) -> bool {
+        !self.spawners.is_empty()
+    }
+
+    /// Seconds left before the next wave, None when no wave is coming
+    pub fn next_wave_in(&self) -> Option<f32> {
+        (!self.is_spawning() && self.current < self.total).then(|| self.countdown.remaining_secs())
+    }
+}
+
+fn start_waves(
+    mut commands: Commands,
+    map: Res<GameMap>,
+) {
+    commands.insert_resource(WaveState {
+        current: 0,
+        total: map.waves.waves.len(),
+        countdown: Timer::from_seconds(map.waves.countdown_before(0), TimerMode::Once),
+        victory: false,
+        spawners: Vec::new(),
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_waves(
+    mut commands: Commands,
+    mut waves: ResMut<WaveState>,
+    mut level_complete: EventWriter<LevelCompleteEvent>,
+    mut next_spawn: Local<usize>,
+    targets: Query<(), With<Target>>,
+    enemies: Res<EnemyRegistry>,
+    map: Res<GameMap>,
+    time: Res<Time>,
+) {
+    if waves.victory {
+        return;
+    }
+    if map.is_changed() {
+        // The level may have been reloaded with a different number of waves
+        waves.total = map.waves.waves.len().max(waves.current);
+    }
+
+    if !waves.is_spawning() {
+        // Let the player deal with the last wave before the countdown starts
+        if waves.current > 0 && !targets.is_empty() {
+            return;
+        }
+        if waves.current == waves.total {
+            info!("All {} waves cleared, victory!", waves.total);
+            waves.victory = true;
+            level_complete.send(LevelCompleteEvent);
+            return;
+        }
+
+        waves.countdown.tick(time.delta());
+        if !waves.countdown.finished() {
+            return;
+        }
+        let wave = &map.waves.waves[waves.current];
+        waves.current += 1;
+        info!("Wave {} of {}", waves.current, waves.total);
+        waves.spawners = wave.groups.iter().map(|g| GroupSpawner::new(g, &enemies)).collect();
+        waves.countdown = Timer::from_seconds(map.waves.countdown_before(waves.current), TimerMode::Once);
+    }
+
+    let spawns = &map.paths.spawns;
+    for spawner in waves.spawners.iter_mut() {
+        spawner.timer.tick(time.delta());
+        if !spawner.timer.just_finished() || spawner.is_done() {
+            continue;
+        }
+
+        let spawn = match spawner.group.spawn {
+            Some(i) => spawns[i],
+            None => {
+                // Take turns between the spawn points
+                let i = *next_spawn % spawns.len();
+                *next_spawn = i + 1;
+                spawns[i]
+            }
+        };
+        spawn_target(&mut commands, enemies.get_or_default(&spawner.group.enemy), &map, spawn);
+        spawner.spawned += 1;
+        spawner.timer = Timer::from_seconds(spawner.group.spacing.max(MIN_GROUP_SPACING), TimerMode::Once);
+    }
+    waves.spawners.retain(|s| !s.is_done());
+}
@@ -0,0 +1,47 @@
+use std::process::Command;
+use serde_json::Value;
+
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/ldtk/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn validate_level(args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_validate-level"))
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn exits_0_on_a_valid_level() {
+    assert_eq!(validate_level(&[&fixture("ldtk_1_5_3.ldtk")]), Some(0));
+}
+
+#[test]
+fn exits_1_when_a_level_has_errors() {
+    // The fixture without its Spawn
+    let mut project: Value = serde_json::from_str(&std::fs::read_to_string(fixture("ldtk_1_5_3.ldtk")).unwrap()).unwrap();
+    project["levels"][0]["layerInstances"][0]["entityInstances"]
+        .as_array_mut()
+        .unwrap()
+        .retain(|e| e["__identifier"] != "Spawn");
+    let broken = format!("{}/no_spawn.ldtk", env!("CARGO_TARGET_TMPDIR"));
+    std::fs::write(&broken, project.to_string()).unwrap();
+
+    assert_eq!(validate_level(&[&broken]), Some(1));
+    // One bad file fails the whole run
+    assert_eq!(validate_level(&["--json", &fixture("ldtk_1_5_3.ldtk"), &broken]), Some(1));
+}
+
+#[test]
+fn exits_1_when_a_file_can_not_be_read() {
+    assert_eq!(validate_level(&[&fixture("missing.ldtk")]), Some(1));
+}
+
+#[test]
+fn exits_2_without_level_files() {
+    assert_eq!(validate_level(&[]), Some(2));
+    assert_eq!(validate_level(&["--json"]), Some(2));
+}